@@ -0,0 +1,10 @@
+use std::{fs, io, path::Path};
+
+// Writes a temp file next to the target and renames it over the target,
+// so a crash can't leave half a file behind (or lose the old one)
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
@@ -1,21 +1,34 @@
 use axum::{
+    Extension,
     body::Body,
     extract::{Json, Request},
     http,
-    http::{Response, StatusCode},
+    http::{HeaderMap, Response, StatusCode},
     middleware::Next,
     response::IntoResponse,
 };
 
 use chrono::{Duration, Utc};
-use jsonwebtoken::{Header, TokenData, Validation, decode, encode};
+use jsonwebtoken::{
+    Header, TokenData, Validation, decode, decode_header, encode,
+    jwk::{Jwk, JwkSet},
+};
 use serde_json::json;
 
-use crate::{campus_backend::login::cdlogin_get_jcookie_and_meta, types::LoginResponse};
 use crate::{
-    constants::{JWT_DEC_KEY, JWT_ENC_KEY},
-    encryption::{decrypt, encrypt},
-    types::{CampusLoginData, CdAuthData, Claims, ResponseError},
+    campus_backend::{client::CampusDualClient, login::cdlogin_get_jcookie_and_meta},
+    types::LoginResponse,
+};
+use crate::{
+    constants::{
+        JWT_KEYS, REVIVE_SECRET_HEADER, access_token_ttl_min, jwt_audience, jwt_issuer,
+        refresh_token_ttl_days, revocation_store,
+    },
+    encryption::{decrypt, encrypt, generate_id, seal_password, unseal_password},
+    types::{
+        CampusLoginData, CdAuthData, Claims, ResponseError, SealedPassword, TokenId,
+        TokenRefreshResponse, TokenSession, TokenType,
+    },
 };
 
 impl IntoResponse for ResponseError {
@@ -29,10 +42,14 @@ impl IntoResponse for ResponseError {
 }
 
 impl From<anyhow::Error> for ResponseError {
-    fn from(_: anyhow::Error) -> Self {
-        ResponseError {
-            message: "Internal Server Error".to_string(),
-            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+    fn from(err: anyhow::Error) -> Self {
+        // CampusDualClient errors are anyhow, keep telling unreachable CaDu apart
+        match err.downcast::<reqwest::Error>() {
+            Ok(err) => err.into(),
+            Err(_) => ResponseError {
+                message: "Internal Server Error".to_string(),
+                status_code: StatusCode::INTERNAL_SERVER_ERROR,
+            },
         }
     }
 }
@@ -64,9 +81,17 @@ impl From<serde_json::Error> for ResponseError {
     }
 }
 
-pub fn encode_jwt(cd_auth_data: CdAuthData) -> Result<String, StatusCode> {
+pub fn encode_jwt(
+    cd_auth_data: CdAuthData,
+    sealed_password: SealedPassword,
+    typ: TokenType,
+    session: TokenSession,
+) -> Result<String, StatusCode> {
     let now = Utc::now();
-    let expire: chrono::TimeDelta = Duration::weeks(13);
+    let expire: chrono::TimeDelta = match typ {
+        TokenType::Access => Duration::minutes(access_token_ttl_min()),
+        TokenType::Refresh => Duration::days(refresh_token_ttl_days()),
+    };
     let exp: usize = (now + expire).timestamp() as usize;
     let iat: usize = now.timestamp() as usize;
 
@@ -77,25 +102,97 @@ pub fn encode_jwt(cd_auth_data: CdAuthData) -> Result<String, StatusCode> {
     let claim = Claims {
         iat,
         exp,
+        iss: jwt_issuer().to_string(),
+        aud: jwt_audience().to_string(),
+        typ,
+        jti: session.jti,
+        sub: cd_auth_data.user.clone(),
+        seminar_group: session.seminar_group,
         nonce,
         cipher,
+        sealed_password: sealed_password.0,
     };
 
-    encode(&Header::default(), &claim, JWT_ENC_KEY.get().unwrap())
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    let (key_id, key) = JWT_KEYS.get().unwrap().current();
+    let header = Header {
+        kid: Some(key_id.to_string()),
+        ..Header::new(key.algorithm)
+    };
+    // the current key is always loaded with its private part
+    let encoding_key = key
+        .encoding
+        .as_ref()
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    encode(&header, &claim, encoding_key).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub fn decode_jwt(jwt: String) -> Result<TokenData<Claims>, StatusCode> {
-    let result: Result<TokenData<Claims>, StatusCode> =
-        decode(&jwt, JWT_DEC_KEY.get().unwrap(), &Validation::default())
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-    result
+// Also rejects tokens of the other type, a refresh token is no access token
+pub fn decode_jwt(jwt: &str, typ: TokenType) -> Result<TokenData<Claims>, StatusCode> {
+    // the kid picks the key, so tokens signed before a rotation stay valid
+    let header = decode_header(jwt).map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let key = JWT_KEYS
+        .get()
+        .unwrap()
+        .get(header.kid.as_deref())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    // only the key's own algorithm, so an HS256 token can't pass as signed by a key pair
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[jwt_issuer()]);
+    validation.set_audience(&[jwt_audience()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    let token_data: TokenData<Claims> =
+        decode(jwt, &key.decoding, &validation).map_err(|_| StatusCode::UNAUTHORIZED)?;
+
+    if token_data.claims.typ != typ {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(token_data)
 }
 
 pub async fn authorize(mut req: Request, next: Next) -> Result<Response<Body>, ResponseError> {
-    let auth_header = req.headers().get(http::header::AUTHORIZATION);
+    let claims = bearer_claims(req.headers(), TokenType::Access)?;
+    let cd_auth_data = claims_auth_data(&claims)?;
+
+    req.extensions_mut()
+        .insert(CampusDualClient::from(&cd_auth_data));
+    req.extensions_mut().insert(cd_auth_data);
+    req.extensions_mut()
+        .insert(SealedPassword(claims.sealed_password));
+    req.extensions_mut().insert(TokenId(claims.jti));
+
+    Ok(next.run(req).await)
+}
 
-    let auth_header = match auth_header {
+// Called with the refresh token as bearer, the CampusDual session inside is passed on as is
+pub async fn refresh_token(
+    headers: HeaderMap,
+) -> Result<Json<TokenRefreshResponse>, ResponseError> {
+    let claims = bearer_claims(&headers, TokenType::Refresh)?;
+    let cd_auth_data = claims_auth_data(&claims)?;
+
+    let token = encode_jwt(
+        cd_auth_data,
+        SealedPassword(claims.sealed_password),
+        TokenType::Access,
+        TokenSession {
+            jti: claims.jti,
+            seminar_group: claims.seminar_group,
+        },
+    )
+    .map_err(|status_code| ResponseError {
+        message: "Internal Server Error".to_string(),
+        status_code,
+    })?;
+
+    Ok(Json(TokenRefreshResponse { token }))
+}
+
+fn bearer_claims(headers: &HeaderMap, typ: TokenType) -> Result<Claims, ResponseError> {
+    let auth_header = match headers.get(http::header::AUTHORIZATION) {
         Some(header) => header.to_str().map_err(|_| ResponseError {
             message: "Empty header is not allowed".to_string(),
             status_code: StatusCode::FORBIDDEN,
@@ -113,9 +210,9 @@ pub async fn authorize(mut req: Request, next: Next) -> Result<Response<Body>, R
     // maybe i'll need bearer idk
     let (_, token) = (header.next(), header.next());
 
-    let token_data = match decode_jwt(token.unwrap().to_string()) {
-        Ok(data) => data,
-        Err(_) => {
+    let claims = match token.map(|token| decode_jwt(token, typ)) {
+        Some(Ok(token_data)) => token_data.claims,
+        _ => {
             return Err(ResponseError {
                 message: "Invalid JWT".to_string(),
                 status_code: StatusCode::UNAUTHORIZED,
@@ -123,21 +220,42 @@ pub async fn authorize(mut req: Request, next: Next) -> Result<Response<Body>, R
         }
     };
 
-    let cd_auth_data_str = decrypt(&token_data.claims.nonce, &token_data.claims.cipher)?;
-    let cd_auth_data: CdAuthData =
-        serde_json::from_str(&cd_auth_data_str).map_err(|_| ResponseError {
-            message: "Invalid JWT claims".to_string(),
+    if revocation_store().is_revoked(&claims.jti) {
+        return Err(ResponseError {
+            message: "JWT has been revoked".to_string(),
             status_code: StatusCode::UNAUTHORIZED,
-        })?;
+        });
+    }
 
-    req.extensions_mut().insert(cd_auth_data);
+    Ok(claims)
+}
 
-    Ok(next.run(req).await)
+fn claims_auth_data(claims: &Claims) -> Result<CdAuthData, ResponseError> {
+    // an unknown AES key ID or a cipher that doesn't decrypt is the client's token, not our fault
+    decrypt(&claims.nonce, &claims.cipher)
+        .ok()
+        .and_then(|cd_auth_data_str| serde_json::from_str(&cd_auth_data_str).ok())
+        .ok_or(ResponseError {
+            message: "Invalid JWT claims".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        })
 }
 
 pub async fn sign_in(
     Json(login_data): Json<CampusLoginData>,
 ) -> Result<Json<LoginResponse>, StatusCode> {
+    sign_in_session(login_data, generate_id()).await
+}
+
+// Logs in and issues both tokens under the given jti. A revived session keeps its jti,
+// so signing out still revokes every token (and feed) issued since the first sign-in
+pub async fn sign_in_session(
+    login_data: CampusLoginData,
+    jti: String,
+) -> Result<Json<LoginResponse>, StatusCode> {
+    let (sealed_password, revive_secret) = seal_password(&login_data.password)?;
+    let sealed_password = SealedPassword(sealed_password);
+
     // Attempt CD login
     let (cd_auth_data, user_basic_info) = match cdlogin_get_jcookie_and_meta(login_data).await {
         Ok((cd_auth_data, user_basic_info)) => (cd_auth_data, user_basic_info),
@@ -146,12 +264,74 @@ pub async fn sign_in(
         }
     };
 
-    // Generate JWT
-    let token = encode_jwt(cd_auth_data).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Generate JWTs, one jti for both so signing out revokes the whole sign-in
+    let session = TokenSession {
+        jti,
+        seminar_group: user_basic_info.seminar_group.clone(),
+    };
+    let token = encode_jwt(
+        cd_auth_data.clone(),
+        sealed_password.clone(),
+        TokenType::Access,
+        session.clone(),
+    )?;
+    let refresh_token = encode_jwt(cd_auth_data, sealed_password, TokenType::Refresh, session)?;
 
     // Return jsonized JWT
     Ok(Json(LoginResponse {
         token,
+        refresh_token,
         user: user_basic_info,
+        revive_secret,
     }))
 }
+
+// Public keys for other services to verify tokens with, empty with a shared JWT_SECRET
+pub async fn jwks() -> Json<JwkSet> {
+    let mut keys: Vec<Jwk> = JWT_KEYS
+        .get()
+        .unwrap()
+        .iter()
+        .filter_map(|(key_id, key)| {
+            let mut jwk = key.jwk.clone()?;
+            jwk.common.key_id = Some(key_id.to_string());
+            Some(jwk)
+        })
+        .collect();
+    keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+
+    Json(JwkSet { keys })
+}
+
+// Revokes the access token and its refresh token (and every access token refreshed from it)
+pub async fn signout(Extension(token_id): Extension<TokenId>) -> Result<StatusCode, ResponseError> {
+    // remembered until the refresh token would have expired anyway
+    let expires_at = (Utc::now() + Duration::days(refresh_token_ttl_days())).timestamp() as usize;
+
+    revocation_store().revoke(&token_id.0, expires_at)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Opens the sealed password with the revive secret the client sent along
+pub fn revive_password(
+    headers: &HeaderMap,
+    sealed_password: &SealedPassword,
+) -> Result<String, ResponseError> {
+    let revive_secret = revive_secret(headers)?;
+
+    unseal_password(&sealed_password.0, revive_secret).map_err(|_| ResponseError {
+        message: "Invalid revive secret".to_string(),
+        status_code: StatusCode::UNAUTHORIZED,
+    })
+}
+
+pub fn revive_secret(headers: &HeaderMap) -> Result<&str, ResponseError> {
+    headers
+        .get(REVIVE_SECRET_HEADER)
+        .and_then(|secret| secret.to_str().ok())
+        .ok_or(ResponseError {
+            message: "Revive secret is missing".to_string(),
+            status_code: StatusCode::BAD_REQUEST,
+        })
+}
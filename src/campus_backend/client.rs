@@ -0,0 +1,299 @@
+use std::future::Future;
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, FixedOffset};
+use http::header::COOKIE;
+
+use crate::{
+    campus_backend::{
+        login::{cdlogin_get_jcookie_and_meta, erp_login_url},
+        req_client_funcs::{
+            cd_cookie_header, extract_exam_signup_options, extract_exam_verfahren_options,
+            extract_grades, get_client_default, selfservice_url,
+        },
+    },
+    types::{
+        CampusDualGrade, CampusDualSignupOption, CampusDualVerfahrenOptions, CampusLoginData,
+        CampusReminders, CampusTimeline, CdAuthData, CdExamDetails, CdExamStats, CdGradeStatEntry,
+        ExamRegistrationMetadata, StundenplanItem, SubGradeMetadata, TimelineEvent,
+        TimelineEventKind, UserBasicInfo,
+    },
+};
+
+// Everything the API reads from or sends to CampusDual. Handlers are generic over this,
+// so they can be tested against a fake instead of a (mocked) CampusDual
+pub trait CampusDual: Clone + Send + Sync + 'static {
+    fn user(&self) -> &str;
+
+    // false if CampusDual wants a new login
+    fn session_alive(&self) -> impl Future<Output = Result<bool>> + Send;
+
+    fn grades(&self) -> impl Future<Output = Result<Vec<CampusDualGrade>>> + Send;
+
+    fn grade_distribution(
+        &self,
+        meta: &SubGradeMetadata,
+    ) -> impl Future<Output = Result<Vec<CdGradeStatEntry>>> + Send;
+
+    fn exam_signups(&self) -> impl Future<Output = Result<Vec<CampusDualSignupOption>>> + Send;
+
+    fn exam_verfahren(&self) -> impl Future<Output = Result<CampusDualVerfahrenOptions>> + Send;
+
+    fn exam_details(
+        &self,
+        meta: &ExamRegistrationMetadata,
+    ) -> impl Future<Output = Result<CdExamDetails>> + Send;
+
+    fn register_exam(
+        &self,
+        meta: &ExamRegistrationMetadata,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    fn cancel_exam(
+        &self,
+        meta: &ExamRegistrationMetadata,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    // raw dash/getcp body
+    fn ects(&self) -> impl Future<Output = Result<String>> + Send;
+
+    // raw dash/getfs body
+    fn fachsem(&self) -> impl Future<Output = Result<String>> + Send;
+
+    fn exam_stats(&self) -> impl Future<Output = Result<CdExamStats>> + Send;
+
+    // start and end are unix seconds
+    fn timetable(
+        &self,
+        start: i64,
+        end: i64,
+    ) -> impl Future<Output = Result<Vec<StundenplanItem>>> + Send;
+
+    fn reminders(&self) -> impl Future<Output = Result<CampusReminders>> + Send;
+
+    // None if CampusDual answered, but not with a parseable timeline
+    fn timeline(&self) -> impl Future<Output = Result<Option<Vec<TimelineEvent>>>> + Send;
+}
+
+// Owns one CampusDual session (cookie + hash) of one user
+#[derive(Debug, Clone)]
+pub struct CampusDualClient {
+    cookie: String,
+    hash: String,
+    user: String,
+}
+
+impl CampusDualClient {
+    pub fn new(cookie: String, hash: String, user: String) -> Self {
+        CampusDualClient { cookie, hash, user }
+    }
+
+    pub async fn login(login_data: CampusLoginData) -> Result<(Self, UserBasicInfo)> {
+        let (cd_auth_data, user_basic_info) = cdlogin_get_jcookie_and_meta(login_data).await?;
+
+        Ok((Self::from(&cd_auth_data), user_basic_info))
+    }
+
+    async fn get_session_text(&self, path_and_query: &str) -> Result<String> {
+        Ok(get_client_default(true)
+            .get(selfservice_url(path_and_query))
+            .header(COOKIE, cd_cookie_header(&self.cookie)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    }
+
+    async fn get_text(&self, path_and_query: &str) -> Result<String> {
+        Ok(get_client_default(true)
+            .get(selfservice_url(path_and_query))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?)
+    }
+}
+
+impl From<&CdAuthData> for CampusDualClient {
+    fn from(cd_auth_data: &CdAuthData) -> Self {
+        CampusDualClient::new(
+            cd_auth_data.cookie.clone(),
+            cd_auth_data.hash.clone(),
+            cd_auth_data.user.clone(),
+        )
+    }
+}
+
+impl CampusDual for CampusDualClient {
+    fn user(&self) -> &str {
+        &self.user
+    }
+
+    async fn session_alive(&self) -> Result<bool> {
+        let resp = get_client_default(false)
+            .get(erp_login_url())
+            .header(COOKIE, cd_cookie_header(&self.cookie)?)
+            .send()
+            .await?;
+
+        // the login page (200) means the session is gone, a live one gets a 500
+        match resp.status().as_u16() {
+            200 => Ok(false),
+            500 => Ok(true),
+            status => bail!("CD healthcheck failed with {status}"),
+        }
+    }
+
+    async fn grades(&self) -> Result<Vec<CampusDualGrade>> {
+        let grade_html = self.get_session_text("/acwork/index").await?;
+
+        extract_grades(grade_html)
+    }
+
+    async fn grade_distribution(&self, meta: &SubGradeMetadata) -> Result<Vec<CdGradeStatEntry>> {
+        let stats = self
+            .get_session_text(&format!(
+                "/acwork/mscoredist?module={}&peryr={}&perid={}",
+                meta.module, meta.peryr, meta.perid
+            ))
+            .await?;
+
+        Ok(serde_json::from_str(&stats)?)
+    }
+
+    async fn exam_signups(&self) -> Result<Vec<CampusDualSignupOption>> {
+        let exam_signup_html = self.get_session_text("/acwork/expproc").await?;
+
+        extract_exam_signup_options(exam_signup_html).await
+    }
+
+    async fn exam_verfahren(&self) -> Result<CampusDualVerfahrenOptions> {
+        let exam_verfahren_html = self.get_session_text("/acwork/cancelproc").await?;
+
+        extract_exam_verfahren_options(exam_verfahren_html).await
+    }
+
+    async fn exam_details(&self, meta: &ExamRegistrationMetadata) -> Result<CdExamDetails> {
+        let details = self
+            .get_text(&format!(
+                "/acwork/offerdetail?user={}&objidexm=undefined&evob_objid={}&peryr={}&perid={}&offerno={}",
+                self.user, meta.assessment, meta.peryr, meta.perid, meta.offerno,
+            ))
+            .await?;
+        let mut exam_details: CdExamDetails = serde_json::from_str(&details)?;
+
+        let examorg_long = match self
+            .get_text(&format!(
+                "/acwork/examorg?examorg={}",
+                exam_details.ev_examorg_text
+            ))
+            .await
+        {
+            Ok(examorg) => serde_json::from_str(&examorg)?,
+            Err(_) => exam_details.ev_examorg_text.clone(),
+        };
+
+        exam_details.ev_examorg_longtext = Some(examorg_long);
+        Ok(exam_details)
+    }
+
+    async fn register_exam(&self, meta: &ExamRegistrationMetadata) -> Result<String> {
+        self.get_text(&format!(
+            "/acwork/registerexam?userid={}&assessment={}&peryr={}&perid={}&offerno={}&hash={}",
+            self.user, meta.assessment, meta.peryr, meta.perid, meta.offerno, self.hash,
+        ))
+        .await
+    }
+
+    async fn cancel_exam(&self, meta: &ExamRegistrationMetadata) -> Result<String> {
+        self.get_text(&format!(
+            "/acwork/cancelexam?userid={}&objid={}&hash={}",
+            self.user, meta.assessment, self.hash
+        ))
+        .await
+    }
+
+    async fn ects(&self) -> Result<String> {
+        self.get_text(&format!(
+            "/dash/getcp?user={}&hash={}",
+            self.user, self.hash
+        ))
+        .await
+    }
+
+    async fn fachsem(&self) -> Result<String> {
+        self.get_text(&format!(
+            "/dash/getfs?user={}&hash={}",
+            self.user, self.hash
+        ))
+        .await
+    }
+
+    async fn exam_stats(&self) -> Result<CdExamStats> {
+        let stats = self
+            .get_text(&format!(
+                "/dash/getexamstats?user={}&hash={}",
+                self.user, self.hash
+            ))
+            .await?;
+
+        Ok(serde_json::from_str(&stats)?)
+    }
+
+    async fn timetable(&self, start: i64, end: i64) -> Result<Vec<StundenplanItem>> {
+        let stundenplan = self
+            .get_text(&format!(
+                "/room/json?userid={}&hash={}&start={start}&end={end}",
+                self.user, self.hash
+            ))
+            .await?;
+
+        Ok(serde_json::from_str(&stundenplan)?)
+    }
+
+    async fn reminders(&self) -> Result<CampusReminders> {
+        let reminders = self
+            .get_text(&format!(
+                "/dash/getreminders?user={}&hash={}",
+                self.user, self.hash
+            ))
+            .await?;
+
+        Ok(serde_json::from_str(&reminders)?)
+    }
+
+    async fn timeline(&self) -> Result<Option<Vec<TimelineEvent>>> {
+        let timeline = self
+            .get_text(&format!("/dash/gettimeline?user={}", self.user))
+            .await?;
+
+        Ok(serde_json::from_str::<CampusTimeline>(&timeline)
+            .ok()
+            .map(|timeline| {
+                timeline
+                    .events
+                    .into_iter()
+                    .map(|event| TimelineEvent {
+                        kind: TimelineEventKind::from_cd_color(&event.color),
+                        name: event.title,
+                        description: event
+                            .description
+                            .replace("<br>", " ")
+                            .replace("<strong>", "")
+                            .replace("</strong>", ""),
+                        color: event.color,
+                        start: parse_campusdate(event.start.as_deref()),
+                        end: parse_campusdate(event.end.as_deref()),
+                    })
+                    .collect()
+            }))
+    }
+}
+
+fn parse_campusdate(input: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    let format = "%a, %d %b %Y %H:%M:%S %z";
+
+    input.and_then(|input| DateTime::parse_from_str(input, format).ok())
+}
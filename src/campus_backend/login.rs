@@ -1,24 +1,54 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use regex::Regex;
-use std::sync::Arc;
+use std::{fmt, sync::Arc};
 
 use lazy_static::lazy_static;
-use reqwest::Client;
+use reqwest::{Client, Url};
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
 use scraper::{Html, Selector};
 
 use crate::{
-    constants::CD_CERT_PEM,
+    campus_backend::req_client_funcs::selfservice_url,
+    constants::{cd_cert, cd_cookie_domain, cd_erp_url},
     types::{CampusLoginData, CdAuthData, UserBasicInfo},
 };
 
+const ZBA_INITSS_PATH: &str = "/sap/bc/webdynpro/sap/zba_initss";
+
+// SAP login page, if the session cookie is still valid it answers with a 500 instead
+pub fn erp_login_url() -> String {
+    format!(
+        "{}{ZBA_INITSS_PATH}?sap-client=100&sap-language=de&uri={}",
+        cd_erp_url(),
+        selfservice_url("/index/login")
+    )
+}
+
+// CampusDual answered the credentials with the login form again instead of a session
+// cookie, e.g. after a password change. Retrying only risks locking the account,
+// unlike every other login error
+#[derive(Debug)]
+pub struct LoginRejected;
+
+impl fmt::Display for LoginRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CampusDual rejected the credentials")
+    }
+}
+
+impl std::error::Error for LoginRejected {}
+
+pub fn is_login_rejected(err: &anyhow::Error) -> bool {
+    err.is::<LoginRejected>()
+}
+
 pub async fn cdlogin_get_jcookie_and_meta(
     login_data: CampusLoginData,
 ) -> Result<(CdAuthData, UserBasicInfo)> {
     let cookie_store = Arc::new(CookieStoreMutex::new(CookieStore::new()));
 
     let client = reqwest::Client::builder()
-        .add_root_certificate(CD_CERT_PEM.get().unwrap().clone())
+        .add_root_certificate(cd_cert().clone())
         .cookie_provider(cookie_store.clone())
         .build()?;
 
@@ -30,7 +60,6 @@ pub async fn cdlogin_get_jcookie_and_meta(
         cookie: extract_cd_cookie(cookie_store)?,
         hash,
         user: login_data.username,
-        password: login_data.password,
     };
 
     Ok((cd_auth_data, user_basic_info))
@@ -38,7 +67,7 @@ pub async fn cdlogin_get_jcookie_and_meta(
 
 async fn campus_login(client: &Client, login_data: &CampusLoginData) -> Result<()> {
     let resp = client
-        .get("https://erp.campus-dual.de/sap/bc/webdynpro/sap/zba_initss?sap-client=100&sap-language=de&uri=https://selfservice.campus-dual.de/index/login")
+        .get(erp_login_url())
         .send()
         .await?
         .error_for_status()?;
@@ -62,7 +91,14 @@ async fn campus_login(client: &Client, login_data: &CampusLoginData) -> Result<(
     ];
 
     let resp = client
-        .post("https://erp.campus-dual.de/sap/bc/webdynpro/sap/zba_initss?uri=https%3a%2f%2fselfservice.campus-dual.de%2findex%2flogin&sap-client=100&sap-language=DE")
+        .post(Url::parse_with_params(
+            &format!("{}{ZBA_INITSS_PATH}", cd_erp_url()),
+            &[
+                ("uri", selfservice_url("/index/login").as_str()),
+                ("sap-client", "100"),
+                ("sap-language", "DE"),
+            ],
+        )?)
         .form(&form)
         .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/117.0.0.0 Safari/537.36")
         .send()
@@ -73,13 +109,18 @@ async fn campus_login(client: &Client, login_data: &CampusLoginData) -> Result<(
     // let now = Instant::now();
 
     // if this cookie is set, the login was successful
-    resp.cookies()
-        .find(|c| {
-            c.domain()
-                .map(|domain| domain.contains("campus-dual.de"))
-                .unwrap_or(false)
-        })
-        .context("c-d.de cookie missing")?;
+    let logged_in = resp.cookies().any(|c| {
+        c.domain()
+            .map(|domain| domain.contains(cd_cookie_domain()))
+            .unwrap_or(false)
+    });
+    if !logged_in {
+        // anything but the login form (e.g. a maintenance page) is worth retrying
+        if resp.text().await?.contains(r#"name="sap-login-XSRF""#) {
+            return Err(LoginRejected.into());
+        }
+        bail!("CD login stage 2: c-d.de cookie missing");
+    }
 
     // println!("CD login cookie check: {:.2?}", now.elapsed());
 
@@ -93,7 +134,7 @@ fn extract_cd_cookie(cookie_store: Arc<CookieStoreMutex>) -> Result<String> {
     let store = cookie_store.lock().unwrap();
     let cookie: &cookie_store::Cookie = store
         .iter_unexpired()
-        .find(|c| c.domain().unwrap_or_default().contains("campus-dual.de"))
+        .find(|c| c.domain().unwrap_or_default().contains(cd_cookie_domain()))
         .context("c-d.de cookie missing")?;
 
     Ok(serde_json::to_string(&cookie)?)
@@ -103,7 +144,7 @@ pub async fn get_hash_and_userinfo(client: &Client) -> Result<(String, UserBasic
     let mut user_basic_info = UserBasicInfo::default();
 
     let resp = client
-        .get("https://selfservice.campus-dual.de/index/login")
+        .get(selfservice_url("/index/login"))
         .send()
         .await?
         .error_for_status()?
@@ -1,58 +1,68 @@
-use std::sync::Arc;
+use std::sync::OnceLock;
 
 use anyhow::{Context, Result};
 use chrono::NaiveDate;
-use cookie_store::CookieStore;
+use http::HeaderValue;
 use lazy_static::lazy_static;
 use regex::Regex;
-use reqwest::Url;
-use reqwest_cookie_store::CookieStoreMutex;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
 use scraper::{Html, Selector, selectable::Selectable};
 
 use crate::{
-    constants::CD_CERT_PEM,
+    constants::{cd_cert, cd_selfservice_url},
     types::{
         CampusDualGrade, CampusDualSignupOption, CampusDualSubGrade, CampusDualVerfahrenOption,
-        ExamRegistrationMetadata, GradeResultsTableType, SubGradeMetadata,
+        CampusDualVerfahrenOptions, ExamRegistrationMetadata, Grade, GradeResultsTableType,
+        SubGradeMetadata,
     },
 };
 
-pub fn get_client_default(retry: bool) -> Result<ClientWithMiddleware> {
-    let retries = if retry { 2 } else { 0 };
-
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(retries);
-    Ok(ClientBuilder::new(
-        reqwest::Client::builder()
-            .add_root_certificate(CD_CERT_PEM.get().unwrap().clone())
-            .use_rustls_tls()
-            .build()?,
-    )
-    .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-    .build())
+// path_and_query starts with a slash, e.g. "/acwork/index"
+pub fn selfservice_url(path_and_query: &str) -> String {
+    format!("{}{path_and_query}", cd_selfservice_url())
 }
 
-pub fn get_client_with_cd_cookie(retry: bool, j_cookie: String) -> Result<ClientWithMiddleware> {
-    let retries = if retry { 2 } else { 0 };
+// One pooled client for all CampusDual calls, so connections and TLS sessions are reused.
+// Sessions are not kept in a cookie jar, the cookie is sent per request instead.
+// reqwest drops that header on redirects to another host (e.g. selfservice -> erp),
+// so session requests only work where CampusDual redirects within the same host
+pub fn get_client_default(retry: bool) -> ClientWithMiddleware {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    static CLIENT_RETRY: OnceLock<ClientWithMiddleware> = OnceLock::new();
+    static CLIENT_NO_RETRY: OnceLock<ClientWithMiddleware> = OnceLock::new();
+
+    let with_retries = |retries| {
+        let client = CLIENT.get_or_init(|| {
+            reqwest::Client::builder()
+                .add_root_certificate(cd_cert().clone())
+                .use_rustls_tls()
+                .build()
+                .expect("Unable to build the CampusDual HTTP client")
+        });
+        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(retries);
+
+        ClientBuilder::new(client.clone())
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build()
+    };
 
-    let cookie: cookie_store::Cookie = serde_json::from_str(&j_cookie)?;
-    let cookie_store = Arc::new(CookieStoreMutex::new(CookieStore::new()));
-    {
-        let mut store = cookie_store.lock().unwrap();
-        store.insert(cookie, &Url::parse("https://campus-dual.de")?)?;
+    if retry {
+        CLIENT_RETRY.get_or_init(|| with_retries(2)).clone()
+    } else {
+        CLIENT_NO_RETRY.get_or_init(|| with_retries(0)).clone()
     }
+}
+
+// Cookie header for the session cookie stored in CdAuthData
+pub fn cd_cookie_header(j_cookie: &str) -> Result<HeaderValue> {
+    let cookie: cookie_store::Cookie = serde_json::from_str(j_cookie)?;
 
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(retries);
-    Ok(ClientBuilder::new(
-        reqwest::Client::builder()
-            .add_root_certificate(CD_CERT_PEM.get().unwrap().clone())
-            .cookie_provider(cookie_store)
-            .use_rustls_tls()
-            .build()?,
-    )
-    .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-    .build())
+    Ok(HeaderValue::from_str(&format!(
+        "{}={}",
+        cookie.name(),
+        cookie.value()
+    ))?)
 }
 
 pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
@@ -91,11 +101,12 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
             .unwrap_or("Kein Name")
             .to_string();
 
-        let grade = table_fields
-            .grade_el
-            .and_then(|el| el.text().next())
-            .unwrap_or("?")
-            .to_string();
+        let grade = Grade::parse(
+            table_fields
+                .grade_el
+                .and_then(|el| el.text().next())
+                .unwrap_or_default(),
+        );
 
         let total_passed = table_fields
             .passed_el
@@ -131,11 +142,12 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
                     .unwrap_or("kein Name")
                     .to_string(),
 
-                grade: sub_table_fields
-                    .grade_el
-                    .and_then(|el| el.text().next())
-                    .unwrap_or("?")
-                    .to_string(),
+                grade: Grade::parse(
+                    sub_table_fields
+                        .grade_el
+                        .and_then(|el| el.text().next())
+                        .unwrap_or_default(),
+                ),
 
                 passed: sub_table_fields
                     .passed_el
@@ -146,13 +158,11 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
                 beurteilung: sub_table_fields
                     .beurteilung_el
                     .and_then(|el| el.text().next())
-                    .unwrap_or("01.01.1970")
-                    .to_string(),
+                    .and_then(parse_grade_date),
                 bekanntgabe: sub_table_fields
                     .bekanntgabe_el
                     .and_then(|el| el.text().next())
-                    .unwrap_or("01.01.1970")
-                    .to_string(),
+                    .and_then(parse_grade_date),
                 wiederholung: sub_table_fields
                     .wiederholung_el
                     .and_then(|el| el.text().next())
@@ -199,10 +209,12 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
             .map(|name| name.trim())
             .unwrap_or("Ohne Name");
 
-        let grade = content
-            .next()
-            .and_then(|el| el.text().next())
-            .unwrap_or("?");
+        let grade = Grade::parse(
+            content
+                .next()
+                .and_then(|el| el.text().next())
+                .unwrap_or_default(),
+        );
 
         let total_passed = content
             .next()
@@ -213,13 +225,11 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
         let beurteilung = content
             .nth(1)
             .and_then(|el| el.text().next())
-            .unwrap_or("01.01.1970")
-            .to_string();
+            .and_then(parse_grade_date);
         let bekanntgabe = content
             .next()
             .and_then(|el| el.text().next())
-            .unwrap_or("01.01.1970")
-            .to_string();
+            .and_then(parse_grade_date);
         let akad_period = content
             .nth(1)
             .and_then(|el| el.text().next())
@@ -228,7 +238,7 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
 
         let subgrades = vec![CampusDualSubGrade {
             name: name.to_string(),
-            grade: grade.to_string(),
+            grade,
             passed: total_passed,
             beurteilung,
             bekanntgabe,
@@ -239,7 +249,7 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
 
         grades.push(CampusDualGrade {
             name: name.to_string(),
-            grade: grade.to_string(),
+            grade,
             total_passed,
             credit_points: 0,
             akad_period,
@@ -254,19 +264,17 @@ pub fn extract_grades(html_text: String) -> Result<Vec<CampusDualGrade>> {
     Ok(grades)
 }
 
-fn get_newest_subgrade_date(grade: &CampusDualGrade) -> NaiveDate {
-    let newest_subgrade = grade.subgrades.iter().max_by(|a, b| {
-        let date_a = NaiveDate::parse_from_str(&a.bekanntgabe, "%d.%m.%Y").unwrap_or_default();
-        let date_b = NaiveDate::parse_from_str(&b.bekanntgabe, "%d.%m.%Y").unwrap_or_default();
-
-        date_a.cmp(&date_b)
-    });
+// None (not announced yet) sorts before any date
+fn get_newest_subgrade_date(grade: &CampusDualGrade) -> Option<NaiveDate> {
+    grade
+        .subgrades
+        .iter()
+        .filter_map(|subgrade| subgrade.bekanntgabe)
+        .max()
+}
 
-    if let Some(subgrade) = newest_subgrade {
-        NaiveDate::parse_from_str(&subgrade.bekanntgabe, "%d.%m.%Y").unwrap_or_default()
-    } else {
-        NaiveDate::default()
-    }
+fn parse_grade_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text.trim(), "%d.%m.%Y").ok()
 }
 
 pub async fn extract_exam_signup_options(html_text: String) -> Result<Vec<CampusDualSignupOption>> {
@@ -421,7 +429,7 @@ pub async fn extract_exam_signup_options(html_text: String) -> Result<Vec<Campus
 
 pub async fn extract_exam_verfahren_options(
     html_text: String,
-) -> Result<Vec<CampusDualVerfahrenOption>> {
+) -> Result<CampusDualVerfahrenOptions> {
     lazy_static! {
         static ref IMG_SEL: Selector = Selector::parse("img").unwrap();
         static ref TABLE_SEL: Selector = Selector::parse("#exopen tbody").unwrap();
@@ -431,23 +439,20 @@ pub async fn extract_exam_verfahren_options(
     };
 
     let mut signup_options = Vec::new();
+    // per-row parse problems, returned to the client instead of failing the whole table
+    let mut warnings = Vec::new();
 
     let document = Html::parse_document(&html_text);
     let table = match document.select(&TABLE_SEL).next() {
         Some(table) => table,
         None => {
             log::warn!("extract_exam_verfahren: no table");
-            return Ok(vec![]);
+            return Ok(CampusDualVerfahrenOptions::default());
         }
     };
 
     let top_level_lines = table.select(&NORMAL_LINE_SEL);
-    for line in top_level_lines {
-        let l_id = match line.value().attr("id") {
-            Some(l_id) => l_id,
-            None => continue,
-        };
-
+    for (row, line) in top_level_lines.enumerate() {
         let mut content = line.select(&TD_SEL);
 
         let name = content
@@ -455,40 +460,65 @@ pub async fn extract_exam_verfahren_options(
             .and_then(|el| el.text().next())
             .unwrap_or("Kein Name")
             .to_string();
+
+        let l_id = match line.value().attr("id") {
+            Some(l_id) => l_id,
+            None => {
+                warnings.push(format!("row {} ({name}): no ID, skipped", row + 1));
+                continue;
+            }
+        };
+
         let verfahren = content
             .next()
             .and_then(|el| el.text().next())
             .unwrap_or("Kein Name")
             .to_string();
-        let pruefart = content
-            .next()
-            .and_then(|el| el.text().next())
-            .unwrap()
-            .to_string();
+        let pruefart = match content.next().and_then(|el| el.text().next()) {
+            Some(pruefart) => pruefart.to_string(),
+            None => {
+                warnings.push(format!("{name}: no Prüfungsart"));
+                String::new()
+            }
+        };
 
-        let subline_selector = &Selector::parse(&format!(".child-of-{l_id}")).unwrap();
-        let mut sublines = table.select(subline_selector);
-        let main_subline = sublines.next().unwrap();
+        let subline_selector = match Selector::parse(&format!(".child-of-{l_id}")) {
+            Ok(selector) => selector,
+            Err(_) => {
+                warnings.push(format!("{name}: invalid row ID {l_id:?}, skipped"));
+                continue;
+            }
+        };
+        let mut sublines = table.select(&subline_selector);
+        let main_subline = match sublines.next() {
+            Some(line) => line,
+            None => {
+                warnings.push(format!("{name}: no exam details, skipped"));
+                continue;
+            }
+        };
 
-        let internal_metadata =
-            main_subline
-                .select(&METADATA_SEL)
-                .next()
-                .map(|meta_el| ExamRegistrationMetadata {
-                    assessment: meta_el.value().attr("data-evob_objid").unwrap().to_string(),
-                    peryr: meta_el.value().attr("data-peryr").unwrap().to_string(),
-                    perid: meta_el.value().attr("data-perid").unwrap().to_string(),
-                    offerno: meta_el.value().attr("data-offerno").unwrap().to_string(),
-                });
+        let metadata_el = main_subline.select(&METADATA_SEL).next();
+        let internal_metadata = metadata_el.and_then(|meta_el| {
+            Some(ExamRegistrationMetadata {
+                assessment: meta_el.value().attr("data-evob_objid")?.to_string(),
+                peryr: meta_el.value().attr("data-peryr")?.to_string(),
+                perid: meta_el.value().attr("data-perid")?.to_string(),
+                offerno: meta_el.value().attr("data-offerno")?.to_string(),
+            })
+        });
+        if metadata_el.is_some() && internal_metadata.is_none() {
+            warnings.push(format!("{name}: incomplete cancellation metadata"));
+        }
 
         let status_icon_url = main_subline
             .select(&IMG_SEL)
             .next()
-            .unwrap()
-            .value()
-            .attr("src")
-            .unwrap();
-        let status = match status_icon_url {
+            .and_then(|img| img.value().attr("src"));
+        if status_icon_url.is_none() {
+            warnings.push(format!("{name}: no status icon"));
+        }
+        let status = match status_icon_url.unwrap_or_default() {
             "/images/missed.png" => "🚫",
             "/images/yellow.png" => "📝",
             "/images/exclamation.jpg" => "⚠️",
@@ -518,7 +548,10 @@ pub async fn extract_exam_verfahren_options(
             continue;
         }
 
-        let signup_information_messy = main_subline_texts.next().unwrap().trim_start();
+        let signup_information_messy = main_subline_texts
+            .next()
+            .map(|text| text.trim_start())
+            .unwrap_or_default();
         let signup_information = if let Some(stripped) = signup_information_messy
             .split_once("Prüfungstermin")
             .map(|split| split.0.replace(", ", ""))
@@ -566,5 +599,8 @@ pub async fn extract_exam_verfahren_options(
         });
     }
 
-    Ok(signup_options)
+    Ok(CampusDualVerfahrenOptions {
+        options: signup_options,
+        warnings,
+    })
 }
@@ -1,28 +1,89 @@
 use std::{env, sync::OnceLock};
 
-use jsonwebtoken::{DecodingKey, EncodingKey};
+use chrono_tz::Tz;
 use reqwest::Certificate;
 
-use crate::encryption::{get_aes_from_env, get_jwt_keys_from_env};
+use crate::{
+    encryption::{
+        JwtKey, KeyRing, get_aes_from_env, get_jwt_keys_from_env, get_vault_key_from_env,
+    },
+    revocation::{FileRevocationStore, MemoryRevocationStore, RevocationStore},
+    vault::SecretVault,
+};
 
-pub static JWT_ENC_KEY: OnceLock<EncodingKey> = OnceLock::new();
-pub static JWT_DEC_KEY: OnceLock<DecodingKey> = OnceLock::new();
-pub static AES_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+pub static JWT_KEYS: OnceLock<KeyRing<JwtKey>> = OnceLock::new();
+pub static AES_KEYS: OnceLock<KeyRing<[u8; 32]>> = OnceLock::new();
 pub static CD_CERT_PEM: OnceLock<Certificate> = OnceLock::new();
 pub static RATELIMIT_QUOTA: OnceLock<u32> = OnceLock::new();
 pub static RATELIMIT_RESTORE_INTERVAL_SEC: OnceLock<u64> = OnceLock::new();
 pub static LOGIN_RATELIMIT_QUOTA: OnceLock<u32> = OnceLock::new();
 pub static LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC: OnceLock<u64> = OnceLock::new();
+pub static CD_SELFSERVICE_URL: OnceLock<String> = OnceLock::new();
+pub static CD_ERP_URL: OnceLock<String> = OnceLock::new();
+pub static CD_COOKIE_DOMAIN: OnceLock<String> = OnceLock::new();
+pub static REQUIRED_CREDIT_POINTS: OnceLock<u32> = OnceLock::new();
+pub static ACCESS_TOKEN_TTL_MIN: OnceLock<i64> = OnceLock::new();
+pub static REFRESH_TOKEN_TTL_DAYS: OnceLock<i64> = OnceLock::new();
+pub static JWT_ISSUER: OnceLock<String> = OnceLock::new();
+pub static JWT_AUDIENCE: OnceLock<String> = OnceLock::new();
+pub static REVOCATION_STORE: OnceLock<Box<dyn RevocationStore>> = OnceLock::new();
+pub static VAULT: OnceLock<SecretVault> = OnceLock::new();
+
+// CampusDual timestamps are unix seconds, but "days" and "weeks" are meant in local time
+pub const CD_TIMEZONE: Tz = chrono_tz::Europe::Berlin;
+// upper bound for /get_stundenplan?from=..&to=.. (inclusive, in days)
+pub const STUNDENPLAN_MAX_SPAN_DAYS: i64 = 186;
+// window served by subscribed timetable feeds, relative to today
+pub const FEED_STUNDENPLAN_DAYS_BEFORE: u64 = 28;
+pub const FEED_STUNDENPLAN_DAYS_AFTER: u64 = 150;
+// carries the revive secret from the login response on re-authenticating calls
+pub const REVIVE_SECRET_HEADER: &str = "x-revive-secret";
+
+// key ID of JWT_SECRET and AES_KEY unless JWT_KEY_ID/AES_KEY_ID say otherwise,
+// also assumed for tokens that were issued before key IDs existed
+pub const DEFAULT_KEY_ID: &str = "1";
+
+pub const DEFAULT_CD_SELFSERVICE_URL: &str = "https://selfservice.campus-dual.de";
+pub const DEFAULT_CD_ERP_URL: &str = "https://erp.campus-dual.de";
+pub const DEFAULT_CD_COOKIE_DOMAIN: &str = "campus-dual.de";
+// ECTS of a bachelor's degree at the BA Sachsen
+pub const DEFAULT_REQUIRED_CREDIT_POINTS: u32 = 180;
+// access tokens are sent with every request, refresh tokens only to /token/refresh
+pub const DEFAULT_ACCESS_TOKEN_TTL_MIN: i64 = 60;
+pub const DEFAULT_REFRESH_TOKEN_TTL_DAYS: i64 = 91;
+pub const MAX_ACCESS_TOKEN_TTL_MIN: i64 = 24 * 60;
+pub const MAX_REFRESH_TOKEN_TTL_DAYS: i64 = 365;
+pub const DEFAULT_JWT_ISSUER: &str = "campus-api";
+pub const DEFAULT_JWT_AUDIENCE: &str = "campus-unbloat";
+
+// The CampusDual accessors fall back to the defaults, so library users
+// don't have to call set_statics_from_env first
+pub fn cd_cert() -> &'static Certificate {
+    CD_CERT_PEM.get_or_init(|| {
+        Certificate::from_pem(include_bytes!("GEANT TLS RSA 1.pem"))
+            .expect("bundled GEANT certificate is valid PEM")
+    })
+}
+
+pub fn cd_selfservice_url() -> &'static str {
+    CD_SELFSERVICE_URL.get_or_init(|| DEFAULT_CD_SELFSERVICE_URL.to_string())
+}
+
+pub fn cd_erp_url() -> &'static str {
+    CD_ERP_URL.get_or_init(|| DEFAULT_CD_ERP_URL.to_string())
+}
+
+pub fn cd_cookie_domain() -> &'static str {
+    CD_COOKIE_DOMAIN.get_or_init(|| DEFAULT_CD_COOKIE_DOMAIN.to_string())
+}
 
 pub fn set_statics_from_env() {
-    AES_KEY.set(get_aes_from_env()).unwrap();
-    let (jwt_enc_key, jwt_dec_key) = get_jwt_keys_from_env();
-    JWT_ENC_KEY
-        .set(jwt_enc_key)
-        .unwrap_or_else(|_| panic!("Unable to set JWT enc key"));
-    JWT_DEC_KEY
-        .set(jwt_dec_key)
-        .unwrap_or_else(|_| panic!("Unable to set JWT dec key"));
+    AES_KEYS
+        .set(get_aes_from_env())
+        .unwrap_or_else(|_| panic!("Unable to set AES keys"));
+    JWT_KEYS
+        .set(get_jwt_keys_from_env())
+        .unwrap_or_else(|_| panic!("Unable to set JWT keys"));
     RATELIMIT_QUOTA
         .set(
             env::var("RATELIMIT_QUOTA")
@@ -52,4 +113,133 @@ pub fn set_statics_from_env() {
                 .unwrap_or(10),
         )
         .unwrap();
+
+    // CampusDual origins, can point to a mock or another institution's CampusDual
+    CD_SELFSERVICE_URL
+        .set(origin_from_env(
+            "CD_SELFSERVICE_URL",
+            DEFAULT_CD_SELFSERVICE_URL,
+        ))
+        .unwrap();
+    CD_ERP_URL
+        .set(origin_from_env("CD_ERP_URL", DEFAULT_CD_ERP_URL))
+        .unwrap();
+    CD_COOKIE_DOMAIN
+        .set(env::var("CD_COOKIE_DOMAIN").unwrap_or(DEFAULT_CD_COOKIE_DOMAIN.to_string()))
+        .unwrap();
+
+    REQUIRED_CREDIT_POINTS
+        .set(
+            env::var("REQUIRED_CREDIT_POINTS")
+                .and_then(|key| key.parse().map_err(|_| env::VarError::NotPresent))
+                .unwrap_or(DEFAULT_REQUIRED_CREDIT_POINTS),
+        )
+        .unwrap();
+
+    ACCESS_TOKEN_TTL_MIN
+        .set(ttl_from_env(
+            "ACCESS_TOKEN_TTL_MIN",
+            DEFAULT_ACCESS_TOKEN_TTL_MIN,
+            MAX_ACCESS_TOKEN_TTL_MIN,
+        ))
+        .unwrap();
+    REFRESH_TOKEN_TTL_DAYS
+        .set(ttl_from_env(
+            "REFRESH_TOKEN_TTL_DAYS",
+            DEFAULT_REFRESH_TOKEN_TTL_DAYS,
+            MAX_REFRESH_TOKEN_TTL_DAYS,
+        ))
+        .unwrap();
+    JWT_ISSUER
+        .set(env::var("JWT_ISSUER").unwrap_or(DEFAULT_JWT_ISSUER.to_string()))
+        .unwrap();
+    JWT_AUDIENCE
+        .set(env::var("JWT_AUDIENCE").unwrap_or(DEFAULT_JWT_AUDIENCE.to_string()))
+        .unwrap();
+
+    if let Ok(path) = env::var("REVOCATION_STORE_FILE") {
+        match FileRevocationStore::open(&path) {
+            Ok(store) => {
+                REVOCATION_STORE
+                    .set(Box::new(store))
+                    .unwrap_or_else(|_| panic!("Unable to set revocation store"));
+            }
+            Err(err) => {
+                log::error!("Unable to open revocation store {path}: {err:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Ok(path) = env::var("VAULT_FILE") {
+        let Some(key) = get_vault_key_from_env() else {
+            log::error!("VAULT_FILE needs VAULT_KEY");
+            std::process::exit(1);
+        };
+        match SecretVault::open(key, &path) {
+            Ok(vault) => {
+                VAULT
+                    .set(vault)
+                    .unwrap_or_else(|_| panic!("Unable to set vault"));
+            }
+            Err(err) => {
+                log::error!("Unable to open vault {path}: {err:#}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+pub fn access_token_ttl_min() -> i64 {
+    *ACCESS_TOKEN_TTL_MIN.get_or_init(|| DEFAULT_ACCESS_TOKEN_TTL_MIN)
+}
+
+pub fn refresh_token_ttl_days() -> i64 {
+    *REFRESH_TOKEN_TTL_DAYS.get_or_init(|| DEFAULT_REFRESH_TOKEN_TTL_DAYS)
+}
+
+pub fn jwt_issuer() -> &'static str {
+    JWT_ISSUER.get_or_init(|| DEFAULT_JWT_ISSUER.to_string())
+}
+
+pub fn jwt_audience() -> &'static str {
+    JWT_AUDIENCE.get_or_init(|| DEFAULT_JWT_AUDIENCE.to_string())
+}
+
+// In-memory unless REVOCATION_STORE_FILE is set
+pub fn revocation_store() -> &'static dyn RevocationStore {
+    REVOCATION_STORE
+        .get_or_init(|| Box::new(MemoryRevocationStore::default()))
+        .as_ref()
+}
+
+// In-memory with a random key unless VAULT_FILE is set
+pub fn vault() -> &'static SecretVault {
+    VAULT.get_or_init(SecretVault::in_memory)
+}
+
+pub fn required_credit_points() -> u32 {
+    *REQUIRED_CREDIT_POINTS.get_or_init(|| DEFAULT_REQUIRED_CREDIT_POINTS)
+}
+
+// Unlike the other numbers, a bad lifetime or interval is an error: zero or negative ones
+// issue dead tokens (or panic timers) and huge ones overflow when computing the expiry
+pub fn ttl_from_env(var: &str, default: i64, max: i64) -> i64 {
+    let Ok(ttl) = env::var(var) else {
+        return default;
+    };
+
+    match ttl.parse() {
+        Ok(ttl) if (1..=max).contains(&ttl) => ttl,
+        _ => {
+            log::error!("{var} must be a whole number from 1 to {max}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn origin_from_env(var: &str, default: &str) -> String {
+    env::var(var)
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or(default.to_string())
 }
@@ -1,13 +1,20 @@
 use aes_gcm::aead::{Aead, KeyInit};
 use aes_gcm::{Aes256Gcm, Key, Nonce};
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use base64::prelude::*;
 use http::StatusCode;
-use jsonwebtoken::{DecodingKey, EncodingKey};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, DecodingKeyKind, EncodingKey,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
 use rand::RngExt;
-use std::{env, str};
+use simple_asn1::ASN1Block;
+use std::{collections::HashMap, env, fs, str};
 
-use crate::constants::AES_KEY;
+use crate::constants::{AES_KEYS, DEFAULT_KEY_ID};
 
 fn generate_nonce() -> [u8; 12] {
     let mut nonce = [0u8; 12];
@@ -16,8 +23,106 @@ fn generate_nonce() -> [u8; 12] {
     nonce
 }
 
+// The cipher is prefixed with the key ID ("id:cipher")
 pub fn encrypt(plaintext: &str) -> Result<(String, String), StatusCode> {
-    let key = AES_KEY.get().unwrap();
+    let (key_id, key) = AES_KEYS.get().unwrap().current();
+    let (nonce, ciphertext) = encrypt_bytes(key, plaintext)?;
+
+    Ok((
+        BASE64_STANDARD.encode(nonce),
+        format!("{key_id}:{}", BASE64_STANDARD.encode(ciphertext)),
+    ))
+}
+
+pub fn decrypt(nonce: &str, ciphertext: &str) -> Result<String> {
+    let (key_id, ciphertext) = match ciphertext.split_once(':') {
+        Some((key_id, ciphertext)) => (Some(key_id), ciphertext),
+        None => (None, ciphertext),
+    };
+    let nonce = BASE64_STANDARD.decode(nonce)?;
+    let ciphertext = BASE64_STANDARD.decode(ciphertext)?;
+
+    decrypt_bytes(aes_key(key_id)?, &nonce, &ciphertext)
+}
+
+// Same envelope as encrypt, but as a single URL-safe string ("id.nonce.cipher")
+pub fn encrypt_urlsafe(plaintext: &str) -> Result<String, StatusCode> {
+    let (key_id, key) = AES_KEYS.get().unwrap().current();
+
+    Ok(format!(
+        "{key_id}.{}",
+        encrypt_urlsafe_with(key, plaintext)?
+    ))
+}
+
+pub fn decrypt_urlsafe(token: &str) -> Result<String> {
+    // tokens from before key IDs are just "nonce.cipher"
+    let (key_id, token) = match token.split('.').count() {
+        3 => token
+            .split_once('.')
+            .map(|(key_id, token)| (Some(key_id), token))
+            .unwrap(),
+        _ => (None, token),
+    };
+
+    decrypt_urlsafe_with(aes_key(key_id)?, token)
+}
+
+fn aes_key(key_id: Option<&str>) -> Result<&'static [u8; 32]> {
+    AES_KEYS
+        .get()
+        .unwrap()
+        .get(key_id)
+        .context("unknown AES key ID")
+}
+
+// Seals the password under a fresh random key that only the client keeps (the revive
+// secret), so the server's AES key alone can't recover it. Returns (sealed, secret)
+pub fn seal_password(password: &str) -> Result<(String, String), StatusCode> {
+    let mut key = [0u8; 32];
+    rand::rng().fill(&mut key);
+
+    Ok((
+        encrypt_urlsafe_with(&key, password)?,
+        BASE64_URL_SAFE_NO_PAD.encode(key),
+    ))
+}
+
+pub fn unseal_password(sealed: &str, revive_secret: &str) -> Result<String> {
+    let key: [u8; 32] = BASE64_URL_SAFE_NO_PAD
+        .decode(revive_secret)?
+        .try_into()
+        .map_err(|_| anyhow!("invalid revive secret length"))?;
+
+    decrypt_urlsafe_with(&key, sealed)
+}
+
+pub fn encrypt_urlsafe_with(key: &[u8; 32], plaintext: &str) -> Result<String, StatusCode> {
+    let (nonce, ciphertext) = encrypt_bytes(key, plaintext)?;
+
+    Ok(format!(
+        "{}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(nonce),
+        BASE64_URL_SAFE_NO_PAD.encode(ciphertext)
+    ))
+}
+
+pub fn decrypt_urlsafe_with(key: &[u8; 32], token: &str) -> Result<String> {
+    let (nonce, ciphertext) = token.split_once('.').context("malformed token")?;
+    let nonce = BASE64_URL_SAFE_NO_PAD.decode(nonce)?;
+    let ciphertext = BASE64_URL_SAFE_NO_PAD.decode(ciphertext)?;
+
+    decrypt_bytes(key, &nonce, &ciphertext)
+}
+
+// Random hex ID, e.g. to revoke feed tokens
+pub fn generate_id() -> String {
+    let mut id = [0u8; 16];
+    rand::rng().fill(&mut id);
+    id.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn encrypt_bytes(key: &[u8; 32], plaintext: &str) -> Result<([u8; 12], Vec<u8>), StatusCode> {
     let cipher = Aes256Gcm::new(key.into());
 
     let nonce = generate_nonce();
@@ -25,63 +130,315 @@ pub fn encrypt(plaintext: &str) -> Result<(String, String), StatusCode> {
         .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    Ok((
-        BASE64_STANDARD.encode(nonce),
-        BASE64_STANDARD.encode(ciphertext),
-    ))
+    Ok((nonce, ciphertext))
 }
 
-pub fn decrypt(nonce: &str, ciphertext: &str) -> Result<String> {
-    let key = Key::<Aes256Gcm>::from_slice(AES_KEY.get().unwrap());
-    let cipher = Aes256Gcm::new(key);
+fn decrypt_bytes(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<String> {
+    if nonce.len() != 12 {
+        return Err(anyhow!("invalid nonce length"));
+    }
 
-    let nonce = BASE64_STANDARD.decode(nonce)?;
-    let ciphertext = BASE64_STANDARD.decode(ciphertext)?;
+    let key = Key::<Aes256Gcm>::from_slice(key);
+    let cipher = Aes256Gcm::new(key);
 
     let plaintext = cipher
-        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
         .map_err(|_| anyhow!("decrypt fail"))?;
 
     Ok(String::from_utf8(plaintext)?)
 }
 
-pub fn get_aes_from_env() -> [u8; 32] {
+pub fn get_aes_from_env() -> KeyRing<[u8; 32]> {
     let key = match env::var("AES_KEY") {
         Err(_) => {
             log::error!("Environment variable AES_KEY is missing");
             std::process::exit(1);
         }
-        Ok(key) => {
-            if key.len() < 32 {
-                log::error!("AES_KEY must be at least 32 bytes long.");
+        Ok(key) => key,
+    };
+
+    let mut key_ring = KeyRing::new(
+        key_id_from_env("AES_KEY_ID"),
+        aes_key_from_str("AES_KEY", &key),
+    );
+    for (key_id, key) in previous_keys_from_env("AES_KEYS_PREVIOUS") {
+        let key = aes_key_from_str("AES_KEYS_PREVIOUS", &key);
+        key_ring = add_previous_key(key_ring, "AES_KEYS_PREVIOUS", key_id, key);
+    }
+    key_ring
+}
+
+// Separate from AES_KEY, otherwise the vault would protect nothing
+pub fn get_vault_key_from_env() -> Option<[u8; 32]> {
+    let key = aes_key_from_str("VAULT_KEY", &env::var("VAULT_KEY").ok()?);
+    if AES_KEYS
+        .get()
+        .unwrap()
+        .iter()
+        .any(|(_, aes_key)| *aes_key == key)
+    {
+        log::error!("VAULT_KEY must differ from the AES keys");
+        std::process::exit(1);
+    }
+    Some(key)
+}
+
+pub fn get_jwt_keys_from_env() -> KeyRing<JwtKey> {
+    let key_id = key_id_from_env("JWT_KEY_ID");
+
+    // key pair if configured, so other services can verify tokens without the secret
+    let mut key_ring = match env::var("JWT_PRIVATE_KEY_FILE") {
+        Ok(private_key_file) => {
+            let algorithm = jwt_algorithm_from_env();
+            let Ok(public_key_file) = env::var("JWT_PUBLIC_KEY_FILE") else {
+                log::error!("JWT_PRIVATE_KEY_FILE also needs JWT_PUBLIC_KEY_FILE");
                 std::process::exit(1);
-            } else {
-                key
+            };
+
+            let mut key_ring = KeyRing::new(
+                key_id,
+                jwt_key_from_files(algorithm, Some(&private_key_file), &public_key_file),
+            );
+            for (key_id, public_key_file) in previous_keys_from_env("JWT_PUBLIC_KEYS_PREVIOUS") {
+                let key = jwt_key_from_files(algorithm, None, &public_key_file);
+                key_ring = add_previous_key(key_ring, "JWT_PUBLIC_KEYS_PREVIOUS", key_id, key);
             }
+            key_ring
+        }
+        Err(_) => {
+            let secret = match env::var("JWT_SECRET") {
+                Err(_) => {
+                    log::error!("Environment variable JWT_SECRET is missing");
+                    std::process::exit(1);
+                }
+                Ok(key) => key,
+            };
+
+            KeyRing::new(key_id, JwtKey::from_secret(secret.as_bytes()))
         }
     };
 
-    let key_bytes = key.as_bytes();
-    let mut key_array = [0u8; 32];
-    if key_bytes.len() > 32 {
-        key_array.copy_from_slice(&key_bytes[..32]);
-    } else {
-        key_array.copy_from_slice(key_bytes);
+    // also after switching to a key pair, so tokens signed with the old secret stay valid
+    for (key_id, secret) in previous_keys_from_env("JWT_SECRETS_PREVIOUS") {
+        let key = JwtKey::from_secret(secret.as_bytes());
+        key_ring = add_previous_key(key_ring, "JWT_SECRETS_PREVIOUS", key_id, key);
     }
-    key_array
+    key_ring
 }
 
-pub fn get_jwt_keys_from_env() -> (EncodingKey, DecodingKey) {
-    let secret = match env::var("JWT_SECRET") {
-        Err(_) => {
-            log::error!("Environment variable JWT_SECRET is missing");
+fn add_previous_key<K>(key_ring: KeyRing<K>, var: &str, key_id: String, key: K) -> KeyRing<K> {
+    key_ring.with_previous(key_id, key).unwrap_or_else(|err| {
+        log::error!("{var}: {err}, a new key needs a new ID");
+        std::process::exit(1);
+    })
+}
+
+fn jwt_algorithm_from_env() -> Algorithm {
+    match env::var("JWT_ALGORITHM").as_deref() {
+        Err(_) | Ok("EdDSA") => Algorithm::EdDSA,
+        Ok("RS256") => Algorithm::RS256,
+        Ok(algorithm) => {
+            log::error!("JWT_ALGORITHM must be EdDSA or RS256, not {algorithm}");
             std::process::exit(1);
         }
-        Ok(key) => key,
+    }
+}
+
+fn jwt_key_from_files(
+    algorithm: Algorithm,
+    private_key_file: Option<&str>,
+    public_key_file: &str,
+) -> JwtKey {
+    let read = |path: &str| {
+        fs::read(path).unwrap_or_else(|err| {
+            log::error!("Unable to read JWT key {path}: {err}");
+            std::process::exit(1);
+        })
     };
+    let private_pem = private_key_file.map(read);
 
-    (
-        EncodingKey::from_secret(secret.as_ref()),
-        DecodingKey::from_secret(secret.as_ref()),
+    JwtKey::from_pem(algorithm, private_pem.as_deref(), &read(public_key_file)).unwrap_or_else(
+        |err| {
+            log::error!("Invalid JWT key {public_key_file}: {err:#}");
+            std::process::exit(1);
+        },
     )
 }
+
+// A JWT signing key, the public part of key pairs is served at /.well-known/jwks.json
+pub struct JwtKey {
+    pub algorithm: Algorithm,
+    // only the current key signs, previous ones just verify
+    pub encoding: Option<EncodingKey>,
+    pub decoding: DecodingKey,
+    // None for shared secrets, those must never be published
+    pub jwk: Option<Jwk>,
+}
+
+impl JwtKey {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        JwtKey {
+            algorithm: Algorithm::HS256,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    // Ed25519 (EdDSA) or RSA (RS256) keys as PEM, without the private key it can only verify
+    pub fn from_pem(
+        algorithm: Algorithm,
+        private_pem: Option<&[u8]>,
+        public_pem: &[u8],
+    ) -> Result<Self> {
+        let (encoding, decoding, key_algorithm, parameters) = match algorithm {
+            Algorithm::EdDSA => {
+                let decoding = DecodingKey::from_ed_pem(public_pem)?;
+                let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: BASE64_URL_SAFE_NO_PAD.encode(public_key_bytes(&decoding)?),
+                });
+                let encoding = private_pem.map(EncodingKey::from_ed_pem).transpose()?;
+
+                (encoding, decoding, KeyAlgorithm::EdDSA, parameters)
+            }
+            Algorithm::RS256 => {
+                let decoding = DecodingKey::from_rsa_pem(public_pem)?;
+                let (n, e) = rsa_components(public_key_bytes(&decoding)?)?;
+                let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: BASE64_URL_SAFE_NO_PAD.encode(n),
+                    e: BASE64_URL_SAFE_NO_PAD.encode(e),
+                });
+                let encoding = private_pem.map(EncodingKey::from_rsa_pem).transpose()?;
+
+                (encoding, decoding, KeyAlgorithm::RS256, parameters)
+            }
+            _ => bail!("unsupported JWT algorithm {algorithm:?}"),
+        };
+
+        Ok(JwtKey {
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(key_algorithm),
+                    ..Default::default()
+                },
+                algorithm: parameters,
+            }),
+        })
+    }
+}
+
+// Raw Ed25519 key or PKCS#1 DER, as jsonwebtoken extracted it from the PEM
+fn public_key_bytes(decoding: &DecodingKey) -> Result<&[u8]> {
+    match decoding.kind() {
+        DecodingKeyKind::SecretOrDer(bytes) => Ok(bytes),
+        DecodingKeyKind::RsaModulusExponent { .. } => bail!("unexpected RSA components"),
+    }
+}
+
+// RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+fn rsa_components(der: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    if let [ASN1Block::Sequence(_, fields)] = simple_asn1::from_der(der)?.as_slice()
+        && let [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] = fields.as_slice()
+    {
+        return Ok((n.to_bytes_be().1, e.to_bytes_be().1));
+    }
+
+    bail!("not an RSA public key")
+}
+
+fn aes_key_from_str(var: &str, key: &str) -> [u8; 32] {
+    if key.len() < 32 {
+        log::error!("{var} must be at least 32 bytes long.");
+        std::process::exit(1);
+    }
+
+    let key_bytes = key.as_bytes();
+    let mut key_array = [0u8; 32];
+    key_array.copy_from_slice(&key_bytes[..32]);
+    key_array
+}
+
+// Key IDs end up in tokens and feed URLs, so they are kept URL-safe and without separators
+fn key_id_from_env(var: &str) -> String {
+    let key_id = env::var(var).unwrap_or(DEFAULT_KEY_ID.to_string());
+    if !valid_key_id(&key_id) {
+        log::error!("{var} may only contain letters, digits, '-' and '_'");
+        std::process::exit(1);
+    }
+    key_id
+}
+
+// "id:key,id:key", keys that were rotated out but should still decrypt/verify
+fn previous_keys_from_env(var: &str) -> Vec<(String, String)> {
+    let Ok(previous) = env::var(var) else {
+        return Vec::new();
+    };
+
+    previous
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((key_id, key)) if valid_key_id(key_id) => (key_id.to_string(), key.to_string()),
+            _ => {
+                log::error!("{var} must look like \"id:key,id:key\"");
+                std::process::exit(1);
+            }
+        })
+        .collect()
+}
+
+fn valid_key_id(key_id: &str) -> bool {
+    !key_id.is_empty()
+        && key_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// The current key encrypts and signs, previous ones are only used to decrypt and verify
+// what was issued before a rotation, until they are removed from the environment
+pub struct KeyRing<K> {
+    current_id: String,
+    keys: HashMap<String, K>,
+}
+
+impl<K> KeyRing<K> {
+    pub fn new(current_id: impl Into<String>, current: K) -> Self {
+        let current_id = current_id.into();
+
+        KeyRing {
+            keys: HashMap::from([(current_id.clone(), current)]),
+            current_id,
+        }
+    }
+
+    // Every key needs its own ID, tokens would otherwise be checked against the wrong one
+    pub fn with_previous(mut self, key_id: impl Into<String>, key: K) -> Result<Self> {
+        let key_id = key_id.into();
+        if self.keys.contains_key(&key_id) {
+            bail!("key ID {key_id} is used more than once");
+        }
+
+        self.keys.insert(key_id, key);
+        Ok(self)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &K)> {
+        self.keys.iter().map(|(key_id, key)| (key_id.as_str(), key))
+    }
+
+    pub fn current(&self) -> (&str, &K) {
+        (&self.current_id, &self.keys[&self.current_id])
+    }
+
+    // Anything issued before key IDs existed was made with the DEFAULT_KEY_ID key
+    pub fn get(&self, key_id: Option<&str>) -> Option<&K> {
+        self.keys.get(key_id.unwrap_or(DEFAULT_KEY_ID))
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration as StdDuration, Instant},
+};
+
+use axum::{Extension, Json, extract::Path};
+use chrono::{Days, Duration, Utc};
+use http::{HeaderMap, StatusCode};
+use lazy_static::lazy_static;
+
+use crate::{
+    auth::{revive_password, revive_secret},
+    campus_backend::{
+        client::{CampusDual, CampusDualClient},
+        login::is_login_rejected,
+    },
+    constants::{
+        CD_TIMEZONE, FEED_STUNDENPLAN_DAYS_AFTER, FEED_STUNDENPLAN_DAYS_BEFORE,
+        refresh_token_ttl_days, revocation_store, vault,
+    },
+    encryption::{decrypt_urlsafe, encrypt_urlsafe, generate_id, unseal_password},
+    ical::{Ics, stundenplan_to_ics, timeline_to_ics},
+    services::{fetch_exams_ics, invalid_timeline_error, stundenplan_range},
+    types::{
+        CampusLoginData, CdAuthData, FeedLinks, FeedRevokeRequest, FeedScope, FeedTokenData,
+        ResponseError, SealedPassword, StundenplanQuery, TokenId,
+    },
+};
+
+// Calendar services poll for many users from a few shared IPs,
+// so exam feed re-logins are limited per feed instead
+const EXAM_FEED_LOGIN_INTERVAL: StdDuration = StdDuration::from_secs(5 * 60);
+
+lazy_static! {
+    // by feed ID, dropped once the feed is gone
+    static ref EXAM_FEEDS: Mutex<HashMap<String, ExamFeedState>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct ExamFeedState {
+    // the feed's expiry, the state isn't needed after that
+    exp: usize,
+    // live session, only renewed once CampusDual drops it
+    session: Option<CampusDualClient>,
+    last_login: Option<Instant>,
+    // the login was rejected, so a changed password isn't tried on every poll
+    dead: bool,
+}
+
+pub async fn create_feed(
+    Extension(cd_auth_data): Extension<CdAuthData>,
+    Extension(sealed_password): Extension<SealedPassword>,
+    Extension(token_id): Extension<TokenId>,
+    headers: HeaderMap,
+) -> Result<Json<FeedLinks>, ResponseError> {
+    // a feed whose exams can never log in is useless, so check the secret right away
+    revive_password(&headers, &sealed_password)?;
+
+    let id = generate_id();
+    // a signout revokes the jti for this long, the feed must not outlive that
+    let exp = (Utc::now() + Duration::days(refresh_token_ttl_days())).timestamp() as usize;
+    let token = encode_feed_token(&FeedTokenData {
+        id: id.clone(),
+        jti: token_id.0,
+        exp,
+        scope: FeedScope::Calendar,
+        cd_auth_data,
+        sealed_password,
+        vault_id: vault().insert(revive_secret(&headers)?, Some(exp))?,
+    })?;
+
+    Ok(Json(FeedLinks {
+        stundenplan: format!("/feed/{token}/stundenplan.ics"),
+        exams: format!("/feed/{token}/exams.ics"),
+        timeline: format!("/feed/{token}/timeline.ics"),
+        id,
+        token,
+    }))
+}
+
+pub async fn revoke_feed(
+    Extension(cd_auth_data): Extension<CdAuthData>,
+    Json(revoke_request): Json<FeedRevokeRequest>,
+) -> Result<StatusCode, ResponseError> {
+    let feed = decode_feed_token(&revoke_request.token)?;
+
+    if feed.cd_auth_data.user != cd_auth_data.user {
+        return Err(ResponseError {
+            message: "Feed belongs to another user".to_string(),
+            status_code: StatusCode::FORBIDDEN,
+        });
+    }
+
+    revocation_store().revoke(&feed.id, feed.exp)?;
+    EXAM_FEEDS.lock().unwrap().remove(&feed.id);
+    vault().remove(&feed.vault_id)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_feed_stundenplan(Path(token): Path<String>) -> Result<Ics, ResponseError> {
+    let feed = authorize_feed(&token)?;
+
+    let today = Utc::now().with_timezone(&CD_TIMEZONE).date_naive();
+    let (start, end) = stundenplan_range(&StundenplanQuery {
+        from: today.checked_sub_days(Days::new(FEED_STUNDENPLAN_DAYS_BEFORE)),
+        to: today.checked_add_days(Days::new(FEED_STUNDENPLAN_DAYS_AFTER)),
+    })?;
+    let client = CampusDualClient::from(&feed.cd_auth_data);
+    let stundenplan = client.timetable(start, end).await?;
+
+    Ok(Ics(stundenplan_to_ics(
+        &stundenplan,
+        &feed.cd_auth_data.user,
+    )))
+}
+
+pub async fn get_feed_exams(Path(token): Path<String>) -> Result<Ics, ResponseError> {
+    let feed = authorize_feed(&token)?;
+    let client = exam_feed_session(&feed).await?;
+
+    Ok(Ics(fetch_exams_ics(&client).await?))
+}
+
+// The cookie in the feed token is long dead, exam pages need a live session
+async fn exam_feed_session(feed: &FeedTokenData) -> Result<CampusDualClient, ResponseError> {
+    let (dead, cached) = match EXAM_FEEDS.lock().unwrap().get(&feed.id) {
+        Some(state) => (state.dead, state.session.clone()),
+        None => (false, None),
+    };
+    if dead {
+        return Err(dead_exam_feed_error());
+    }
+    if let Some(client) = cached
+        && client.session_alive().await?
+    {
+        return Ok(client);
+    }
+
+    // a re-login is a sign-in as far as CampusDual is concerned
+    let now = Instant::now();
+    let mut login_allowed = false;
+    update_exam_feed(feed, |state| {
+        if state
+            .last_login
+            .is_none_or(|last_login| now.duration_since(last_login) >= EXAM_FEED_LOGIN_INTERVAL)
+        {
+            state.last_login = Some(now);
+            login_allowed = true;
+        }
+    });
+    if !login_allowed {
+        return Err(ResponseError {
+            message: "Too many logins, try again later".to_string(),
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+        });
+    }
+
+    // gone if the vault is in-memory only and the API restarted
+    let Some(revive_secret) = vault().get(&feed.vault_id) else {
+        return Err(dead_exam_feed_error());
+    };
+    let password = unseal_password(&feed.sealed_password.0, &revive_secret)?;
+    let login = CampusDualClient::login(CampusLoginData {
+        username: feed.cd_auth_data.user.clone(),
+        password,
+    })
+    .await;
+
+    match login {
+        Ok((client, _)) => {
+            update_exam_feed(feed, |state| state.session = Some(client.clone()));
+            Ok(client)
+        }
+        Err(err) if is_login_rejected(&err) => {
+            log::warn!("Exam feed login rejected, giving up on the feed");
+            update_exam_feed(feed, |state| {
+                state.dead = true;
+                state.session = None;
+            });
+            Err(dead_exam_feed_error())
+        }
+        Err(err) => {
+            log::warn!("Exam feed login failed: {err:#}");
+            Err(ResponseError {
+                message: "Failed to log in to CaDu, try again later".to_string(),
+                status_code: StatusCode::BAD_GATEWAY,
+            })
+        }
+    }
+}
+
+fn update_exam_feed(feed: &FeedTokenData, update: impl FnOnce(&mut ExamFeedState)) {
+    let now = Utc::now().timestamp() as usize;
+    let mut exam_feeds = EXAM_FEEDS.lock().unwrap();
+
+    // expired feeds aren't polled successfully anymore, so nothing else would drop them
+    exam_feeds.retain(|_, state| state.exp > now);
+    update(exam_feeds.entry(feed.id.clone()).or_insert(ExamFeedState {
+        exp: feed.exp,
+        ..Default::default()
+    }));
+}
+
+fn dead_exam_feed_error() -> ResponseError {
+    ResponseError {
+        message: "Exam feed can't log in to CaDu anymore (password changed?), create a new feed"
+            .to_string(),
+        status_code: StatusCode::GONE,
+    }
+}
+
+pub async fn get_feed_timeline(Path(token): Path<String>) -> Result<Ics, ResponseError> {
+    let feed = authorize_feed(&token)?;
+
+    let events = CampusDualClient::from(&feed.cd_auth_data)
+        .timeline()
+        .await?
+        .ok_or_else(invalid_timeline_error)?;
+
+    Ok(Ics(timeline_to_ics(&events, &feed.cd_auth_data.user)))
+}
+
+// Only tokens with calendar scope that haven't been revoked get through
+fn authorize_feed(token: &str) -> Result<FeedTokenData, ResponseError> {
+    let feed = decode_feed_token(token)?;
+
+    if feed.scope != FeedScope::Calendar {
+        return Err(ResponseError {
+            message: "Feed token has the wrong scope".to_string(),
+            status_code: StatusCode::FORBIDDEN,
+        });
+    }
+
+    if revocation_store().is_revoked(&feed.id) || revocation_store().is_revoked(&feed.jti) {
+        EXAM_FEEDS.lock().unwrap().remove(&feed.id);
+        return Err(ResponseError {
+            message: "Feed has been revoked".to_string(),
+            status_code: StatusCode::GONE,
+        });
+    }
+
+    if feed.exp <= Utc::now().timestamp() as usize {
+        EXAM_FEEDS.lock().unwrap().remove(&feed.id);
+        return Err(ResponseError {
+            message: "Feed has expired".to_string(),
+            status_code: StatusCode::GONE,
+        });
+    }
+
+    Ok(feed)
+}
+
+fn encode_feed_token(feed: &FeedTokenData) -> Result<String, ResponseError> {
+    encrypt_urlsafe(&serde_json::to_string(feed)?).map_err(|status_code| ResponseError {
+        message: "Internal Server Error".to_string(),
+        status_code,
+    })
+}
+
+fn decode_feed_token(token: &str) -> Result<FeedTokenData, ResponseError> {
+    decrypt_urlsafe(token)
+        .ok()
+        .and_then(|feed_str| serde_json::from_str(&feed_str).ok())
+        .ok_or(ResponseError {
+            message: "Invalid feed token".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        })
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use axum::{Extension, Json};
+use http::StatusCode;
+
+use crate::{
+    campus_backend::client::CampusDual,
+    types::{
+        CampusDualGrade, GpaRequest, GpaStats, GpaSummary, ResponseError, SemesterGpa, WhatIfGrade,
+    },
+};
+
+pub async fn get_gpa<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<GpaSummary>, ResponseError> {
+    let grades = client.grades().await?;
+
+    Ok(Json(gpa_summary(&grades, &[])?))
+}
+
+pub async fn post_gpa_what_if<C: CampusDual>(
+    Extension(client): Extension<C>,
+    Json(request): Json<GpaRequest>,
+) -> Result<Json<GpaSummary>, ResponseError> {
+    let grades = client.grades().await?;
+
+    Ok(Json(gpa_summary(&grades, &request.what_if)?))
+}
+
+// Pass/fail modules only count towards earned credit points, 0 CP rows (Teilprüfungen)
+// not at all. What-if grades replace the grade of modules that aren't passed yet
+pub fn gpa_summary(
+    grades: &[CampusDualGrade],
+    what_if: &[WhatIfGrade],
+) -> Result<GpaSummary, ResponseError> {
+    let bad_request = |message: String| ResponseError {
+        message,
+        status_code: StatusCode::BAD_REQUEST,
+    };
+
+    let mut hypothetical = HashMap::new();
+    for what_if_grade in what_if {
+        if !(1.0..=4.0).contains(&what_if_grade.grade) {
+            return Err(bad_request(format!(
+                "What-if grade for {} must be a passing grade (1.0 to 4.0)",
+                what_if_grade.module
+            )));
+        }
+
+        let pending = grades.iter().any(|grade| {
+            grade.name == what_if_grade.module
+                && grade.credit_points > 0
+                && grade.total_passed != Some(true)
+        });
+        if !pending {
+            return Err(bad_request(format!(
+                "{} is not a pending module",
+                what_if_grade.module
+            )));
+        }
+
+        hypothetical.insert(what_if_grade.module.as_str(), what_if_grade.grade);
+    }
+
+    let mut overall = GpaAccumulator::default();
+    let mut semesters: Vec<(&str, GpaAccumulator)> = Vec::new();
+
+    for grade in grades.iter().filter(|grade| grade.credit_points > 0) {
+        let (passed, numeric_grade) = match hypothetical.get(grade.name.as_str()) {
+            Some(&what_if_grade) => (true, Some(what_if_grade)),
+            None => (grade.total_passed == Some(true), grade.grade.numeric()),
+        };
+        if !passed {
+            continue;
+        }
+
+        let semester = match semesters
+            .iter_mut()
+            .find(|(period, _)| *period == grade.akad_period)
+        {
+            Some((_, semester)) => semester,
+            None => {
+                semesters.push((&grade.akad_period, GpaAccumulator::default()));
+                &mut semesters.last_mut().unwrap().1
+            }
+        };
+
+        for accumulator in [&mut overall, semester] {
+            accumulator.add(grade.credit_points, numeric_grade);
+        }
+    }
+
+    semesters.sort_by_key(|(period, _)| period_sort_key(period));
+
+    Ok(GpaSummary {
+        overall: overall.into(),
+        semesters: semesters
+            .into_iter()
+            .map(|(period, semester)| SemesterGpa {
+                akad_period: period.to_string(),
+                stats: semester.into(),
+            })
+            .collect(),
+        hypothetical: !hypothetical.is_empty(),
+    })
+}
+
+#[derive(Default)]
+struct GpaAccumulator {
+    weighted_sum: f64,
+    graded_credit_points: i32,
+    earned_credit_points: i32,
+    graded_modules: usize,
+}
+
+impl GpaAccumulator {
+    fn add(&mut self, credit_points: i32, grade: Option<f64>) {
+        self.earned_credit_points += credit_points;
+
+        if let Some(grade) = grade {
+            self.weighted_sum += grade * credit_points as f64;
+            self.graded_credit_points += credit_points;
+            self.graded_modules += 1;
+        }
+    }
+}
+
+impl From<GpaAccumulator> for GpaStats {
+    fn from(acc: GpaAccumulator) -> Self {
+        GpaStats {
+            gpa: (acc.graded_credit_points > 0).then(|| {
+                let gpa = acc.weighted_sum / acc.graded_credit_points as f64;
+                (gpa * 100.0).round() / 100.0
+            }),
+            graded_credit_points: acc.graded_credit_points,
+            earned_credit_points: acc.earned_credit_points,
+            graded_modules: acc.graded_modules,
+        }
+    }
+}
+
+// "WS 2022/23" comes after "SS 2022" and before "SS 2023", unknown periods go last
+fn period_sort_key(period: &str) -> (i32, u8) {
+    let mut parts = period.split_whitespace();
+    let term = parts.next();
+    let year = parts
+        .next()
+        .and_then(|year| year.split('/').next())
+        .and_then(|year| year.parse().ok());
+
+    match (term, year) {
+        (Some("SS"), Some(year)) => (year, 0),
+        (Some("WS"), Some(year)) => (year, 1),
+        _ => (i32::MAX, 0),
+    }
+}
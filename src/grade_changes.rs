@@ -0,0 +1,123 @@
+use std::{collections::HashSet, hash::Hasher};
+
+use axum::{Extension, Json, extract::Query};
+use base64::prelude::*;
+use chrono::NaiveDate;
+use fnv::FnvHasher;
+use http::StatusCode;
+
+use crate::{
+    campus_backend::client::CampusDual,
+    types::{CampusDualGrade, CampusDualSubGrade, GradeChanges, GradeChangesQuery, ResponseError},
+};
+
+pub async fn get_grade_changes<C: CampusDual>(
+    Extension(client): Extension<C>,
+    Query(query): Query<GradeChangesQuery>,
+) -> Result<Json<GradeChanges>, ResponseError> {
+    let known = query
+        .fingerprint
+        .as_deref()
+        .map(parse_fingerprint)
+        .transpose()?;
+    let grades = client.grades().await?;
+
+    Ok(Json(GradeChanges {
+        fingerprint: grade_fingerprint(&grades),
+        grades: changed_grades(grades, query.since, known.as_ref()),
+    }))
+}
+
+// The fingerprint is every module's and subgrade's content hash, so a later request
+// can tell which of them changed without the API storing anything
+pub fn grade_fingerprint(grades: &[CampusDualGrade]) -> String {
+    let mut hashes: Vec<u32> = grades
+        .iter()
+        .flat_map(|grade| {
+            std::iter::once(module_hash(grade)).chain(
+                grade
+                    .subgrades
+                    .iter()
+                    .map(|subgrade| subgrade_hash(&grade.name, subgrade)),
+            )
+        })
+        .collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    BASE64_URL_SAFE_NO_PAD.encode(
+        hashes
+            .iter()
+            .flat_map(|hash| hash.to_be_bytes())
+            .collect::<Vec<u8>>(),
+    )
+}
+
+pub fn parse_fingerprint(fingerprint: &str) -> Result<HashSet<u32>, ResponseError> {
+    let invalid_fingerprint = || ResponseError {
+        message: "Invalid grade fingerprint".to_string(),
+        status_code: StatusCode::BAD_REQUEST,
+    };
+
+    let bytes = BASE64_URL_SAFE_NO_PAD
+        .decode(fingerprint)
+        .map_err(|_| invalid_fingerprint())?;
+    if bytes.len() % 4 != 0 {
+        return Err(invalid_fingerprint());
+    }
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|hash| u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]))
+        .collect())
+}
+
+// A subgrade changed if it was announced after `since` or its hash isn't in `known`.
+// A module is kept if it or any of its subgrades changed
+pub fn changed_grades(
+    grades: Vec<CampusDualGrade>,
+    since: Option<NaiveDate>,
+    known: Option<&HashSet<u32>>,
+) -> Vec<CampusDualGrade> {
+    if since.is_none() && known.is_none() {
+        return grades;
+    }
+
+    let is_new = |hash: u32| known.is_some_and(|known| !known.contains(&hash));
+
+    grades
+        .into_iter()
+        .filter_map(|mut grade| {
+            let module_changed = is_new(module_hash(&grade));
+            let name = grade.name.clone();
+
+            grade.subgrades.retain(|subgrade| {
+                since.is_some_and(|since| subgrade.bekanntgabe.is_some_and(|date| date > since))
+                    || is_new(subgrade_hash(&name, subgrade))
+            });
+
+            (module_changed || !grade.subgrades.is_empty()).then_some(grade)
+        })
+        .collect()
+}
+
+fn module_hash(grade: &CampusDualGrade) -> u32 {
+    content_hash(&(
+        &grade.name,
+        &grade.grade,
+        grade.total_passed,
+        grade.credit_points,
+        &grade.akad_period,
+    ))
+}
+
+fn subgrade_hash(module: &str, subgrade: &CampusDualSubGrade) -> u32 {
+    content_hash(&(module, subgrade))
+}
+
+fn content_hash(value: &impl serde::Serialize) -> u32 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(serde_json::to_string(value).unwrap_or_default().as_bytes());
+
+    hasher.finish() as u32
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use axum::{Extension, Json};
+use http::{HeaderMap, StatusCode};
+use lazy_static::lazy_static;
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::Policy,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{net::lookup_host, sync::Semaphore, task::JoinSet, time::Instant};
+
+use crate::{
+    atomic_write::write_atomically,
+    auth::{revive_password, revive_secret},
+    campus_backend::{
+        client::{CampusDual, CampusDualClient},
+        login::is_login_rejected,
+    },
+    constants::{ttl_from_env, vault},
+    encryption::{decrypt_urlsafe, encrypt_urlsafe, unseal_password},
+    grade_changes::{changed_grades, grade_fingerprint, parse_fingerprint},
+    types::{CampusDualGrade, CampusLoginData, CdAuthData, ResponseError, SealedPassword},
+};
+
+const DEFAULT_STORE_PATH: &str = "grade_watcher.store";
+const DEFAULT_INTERVAL_MIN: i64 = 30;
+const MAX_INTERVAL_MIN: i64 = 24 * 60;
+// checks running at once, so a slow user or target doesn't hold up the others
+const MAX_CONCURRENT_CHECKS: usize = 8;
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+const NOTIFY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    // opted-in users by user number, mirrored to the encrypted store file
+    static ref WATCHED_USERS: Mutex<HashMap<String, WatchedUser>> = Mutex::new(HashMap::new());
+    // live sessions by vault ID (one per subscription), only renewed once CampusDual drops them
+    static ref SESSIONS: Mutex<HashMap<String, CampusDualClient>> = Mutex::new(HashMap::new());
+}
+
+static STORE_PATH: OnceLock<PathBuf> = OnceLock::new();
+static INTERVAL_MIN: OnceLock<i64> = OnceLock::new();
+
+// Hosts exempt from the public address check (GRADE_WATCHER_ALLOWED_HOSTS), e.g. a self-hosted ntfy
+pub static ALLOWED_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchedUser {
+    pub cd_auth_data: CdAuthData,
+    // checks run unattended, so the revive secret is kept in the vault
+    pub sealed_password: SealedPassword,
+    pub vault_id: String,
+    pub target: NotifyTarget,
+    // None until the first check, so subscribing doesn't notify about every old grade
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifyTarget {
+    // plain text POST with a Title header, as ntfy.sh expects it
+    Ntfy { url: String },
+    // JSON POST with the changed grades
+    Webhook { url: String },
+}
+
+#[derive(Deserialize)]
+pub struct WatcherSubscribeRequest {
+    pub target: NotifyTarget,
+}
+
+pub enum CheckOutcome {
+    // the new fingerprint
+    Checked(String),
+    // CampusDual didn't accept the password anymore, retrying would only risk a lockout
+    LoginRejected,
+}
+
+enum Notification<'a> {
+    Grades(&'a [CampusDualGrade]),
+    // sent once when a rejected login ended the subscription
+    Disabled,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum WebhookPayload<'a> {
+    Grades {
+        user: &'a str,
+        grades: &'a [CampusDualGrade],
+    },
+    Disabled {
+        user: &'a str,
+    },
+}
+
+pub async fn subscribe(
+    Extension(cd_auth_data): Extension<CdAuthData>,
+    Extension(sealed_password): Extension<SealedPassword>,
+    headers: HeaderMap,
+    Json(request): Json<WatcherSubscribeRequest>,
+) -> Result<StatusCode, ResponseError> {
+    revive_password(&headers, &sealed_password)?;
+    check_target(&request.target).await?;
+
+    let vault_id = vault().insert(revive_secret(&headers)?, None)?;
+    // subscribing again replaces the target
+    forget_user(&cd_auth_data.user, None)?;
+    WATCHED_USERS.lock().unwrap().insert(
+        cd_auth_data.user.clone(),
+        WatchedUser {
+            cd_auth_data,
+            sealed_password,
+            vault_id,
+            target: request.target,
+            fingerprint: None,
+        },
+    );
+    save_store()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unsubscribe(
+    Extension(cd_auth_data): Extension<CdAuthData>,
+) -> Result<StatusCode, ResponseError> {
+    forget_user(&cd_auth_data.user, None)?;
+    save_store()?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Removes the user along with their revive secret, the store still has to be saved.
+// With a vault ID only that subscription, the user may have subscribed again meanwhile
+fn forget_user(user_number: &str, vault_id: Option<&str>) -> Result<bool> {
+    let removed = {
+        let mut users = WATCHED_USERS.lock().unwrap();
+        match users.get(user_number) {
+            Some(user) if vault_id.is_none_or(|vault_id| user.vault_id == vault_id) => {
+                users.remove(user_number)
+            }
+            _ => None,
+        }
+    };
+
+    match removed {
+        Some(user) => {
+            SESSIONS.lock().unwrap().remove(&user.vault_id);
+            vault().remove(&user.vault_id)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Loads the store, has to run before the API serves (un)subscribe requests.
+// Exits if the store can't be read, starting empty would overwrite it on the next save
+pub fn init() {
+    INTERVAL_MIN.get_or_init(|| {
+        ttl_from_env(
+            "GRADE_WATCHER_INTERVAL_MIN",
+            DEFAULT_INTERVAL_MIN,
+            MAX_INTERVAL_MIN,
+        )
+    });
+    // without a file, the revive secrets would be gone after a restart
+    if !vault().is_persistent() {
+        log::error!("The grade watcher needs VAULT_FILE and VAULT_KEY");
+        std::process::exit(1);
+    }
+
+    ALLOWED_HOSTS.get_or_init(|| {
+        env::var("GRADE_WATCHER_ALLOWED_HOSTS")
+            .map(|hosts| {
+                hosts
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+
+    let store_path: PathBuf = env::var("GRADE_WATCHER_STORE")
+        .unwrap_or(DEFAULT_STORE_PATH.to_string())
+        .into();
+
+    match load_store(&store_path) {
+        Ok(users) => *WATCHED_USERS.lock().unwrap() = users,
+        Err(err) => {
+            log::error!(
+                "Unable to load grade watcher store {}: {err:#}",
+                store_path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    log::info!(
+        "Grade watcher: {} users, store {}",
+        WATCHED_USERS.lock().unwrap().len(),
+        store_path.display()
+    );
+    // only now saving is enabled
+    STORE_PATH
+        .set(store_path)
+        .expect("grade watcher initialized twice");
+}
+
+// Checks every opted-in user periodically, runs forever
+pub async fn run() {
+    let interval_min = *INTERVAL_MIN.get().expect("grade watcher isn't initialized");
+    log::info!("Grade watcher: checking every {interval_min} minutes");
+
+    // the first check waits as well, so restarts don't log everyone in at once
+    let period = Duration::from_secs(interval_min as u64 * 60);
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+
+        let users: Vec<WatchedUser> = WATCHED_USERS.lock().unwrap().values().cloned().collect();
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_CHECKS));
+        let mut checks = JoinSet::new();
+        for user in users {
+            let permits = permits.clone();
+            checks.spawn(async move {
+                let _permit = permits.acquire().await;
+                let user_number = user.cd_auth_data.user.clone();
+
+                match check_user(&user).await {
+                    Ok(CheckOutcome::Checked(fingerprint)) => {
+                        // the user may have unsubscribed or subscribed again in the meantime
+                        if let Some(watched) = WATCHED_USERS.lock().unwrap().get_mut(&user_number)
+                            && watched.vault_id == user.vault_id
+                        {
+                            watched.fingerprint = Some(fingerprint);
+                        }
+                    }
+                    Ok(CheckOutcome::LoginRejected) => {
+                        match forget_user(&user_number, Some(&user.vault_id)) {
+                            Ok(true) => {
+                                log::warn!("Grade watcher login rejected, unsubscribed the user");
+                                let notification = Notification::Disabled;
+                                if let Err(err) =
+                                    notify(&user.target, &user_number, notification).await
+                                {
+                                    log::warn!("Grade watcher notification failed: {err:#}");
+                                }
+                            }
+                            Ok(false) => {}
+                            Err(err) => {
+                                log::error!(
+                                    "Unable to remove revive secret from the vault: {err:#}"
+                                )
+                            }
+                        }
+                    }
+                    Err(err) => log::warn!("Grade watcher check failed: {err:#}"),
+                }
+            });
+        }
+        checks.join_all().await;
+
+        if let Err(err) = save_store() {
+            log::error!("Unable to save grade watcher store: {}", err.message);
+        }
+    }
+}
+
+// Notifies about changed grades and returns the new fingerprint
+pub async fn check_user(user: &WatchedUser) -> Result<CheckOutcome> {
+    let Some(client) = user_session(user).await? else {
+        return Ok(CheckOutcome::LoginRejected);
+    };
+    let grades = client.grades().await?;
+    let fingerprint = grade_fingerprint(&grades);
+
+    if let Some(previous) = &user.fingerprint
+        && *previous != fingerprint
+    {
+        let known = parse_fingerprint(previous).map_err(|err| anyhow!(err.message))?;
+        let changed = changed_grades(grades, None, Some(&known));
+
+        if !changed.is_empty() {
+            notify(&user.target, client.user(), Notification::Grades(&changed)).await?;
+        }
+    }
+
+    Ok(CheckOutcome::Checked(fingerprint))
+}
+
+// Reuses the user's session while CampusDual keeps it alive, a re-login is a sign-in as
+// far as CampusDual is concerned. None if the login was rejected
+async fn user_session(user: &WatchedUser) -> Result<Option<CampusDualClient>> {
+    let cached = SESSIONS.lock().unwrap().get(&user.vault_id).cloned();
+    if let Some(client) = cached
+        && client.session_alive().await?
+    {
+        return Ok(Some(client));
+    }
+
+    let login = CampusDualClient::login(CampusLoginData {
+        username: user.cd_auth_data.user.clone(),
+        password: unseal_password(
+            &user.sealed_password.0,
+            &vault()
+                .get(&user.vault_id)
+                .context("revive secret is missing from the vault")?,
+        )?,
+    })
+    .await;
+
+    match login {
+        Ok((client, _)) => {
+            SESSIONS
+                .lock()
+                .unwrap()
+                .insert(user.vault_id.clone(), client.clone());
+            Ok(Some(client))
+        }
+        Err(err) if is_login_rejected(&err) => {
+            SESSIONS.lock().unwrap().remove(&user.vault_id);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// The API must not be usable to reach its own network (loopback, private ranges,
+// cloud metadata at 169.254.169.254, ...), so targets have to resolve to public addresses
+pub async fn check_target(target: &NotifyTarget) -> Result<(), ResponseError> {
+    let (NotifyTarget::Ntfy { url } | NotifyTarget::Webhook { url }) = target;
+    let bad_request = |message: &str| ResponseError {
+        message: message.to_string(),
+        status_code: StatusCode::BAD_REQUEST,
+    };
+
+    let url = Url::parse(url)
+        .ok()
+        .filter(|url| ["http", "https"].contains(&url.scheme()))
+        .ok_or_else(|| bad_request("Notification URL must be an http(s) URL"))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| bad_request("Notification URL must be an http(s) URL"))?;
+    if host_allowed(host) {
+        return Ok(());
+    }
+
+    let addrs: Vec<IpAddr> = match host.trim_matches(['[', ']']).parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => lookup_host((host, url.port_or_known_default().unwrap_or(80)))
+            .await
+            .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+            .unwrap_or_default(),
+    };
+    if addrs.is_empty() || !addrs.into_iter().all(is_public) {
+        return Err(bad_request(
+            "Notification URL must point to a public address",
+        ));
+    }
+
+    Ok(())
+}
+
+fn host_allowed(host: &str) -> bool {
+    ALLOWED_HOSTS.get().is_some_and(|hosts| {
+        hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    })
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // "this network", 0.0.0.0/8
+                || first == 0
+                // CGNAT, 100.64.0.0/10, also used for cloud metadata (100.100.100.200)
+                || (first == 100 && second & 0xc0 == 64)
+                // benchmarking, 198.18.0.0/15
+                || (first == 198 && second & 0xfe == 18)
+                // reserved, 240.0.0.0/4
+                || first >= 240)
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let segments = ip.segments();
+
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    // local-use NAT64, 64:ff9b:1::/48
+                    || segments[..3] == [0x64, 0xff9b, 1]
+                    // documentation, 2001:db8::/32
+                    || segments[..2] == [0x2001, 0xdb8])
+            }
+        },
+    }
+}
+
+// The IPv4 address an IPv6 address stands for: IPv4-mapped (::ffff:0:0/96),
+// IPv4-compatible (::/96), NAT64 (64:ff9b::/96) and 6to4 (2002::/16)
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let ipv4 = |high: u16, low: u16| Some(Ipv4Addr::from((high as u32) << 16 | low as u32));
+
+    match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low]
+        | [0, 0, 0, 0, 0, 0, high, low]
+        | [0x64, 0xff9b, 0, 0, 0, 0, high, low]
+        | [0x2002, high, low, ..] => ipv4(high, low),
+        _ => None,
+    }
+}
+
+// Drops non-public addresses when notifying as well, a host may resolve differently
+// than it did when subscribing
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = lookup_host((host, 0))
+                .await?
+                .filter(|addr| host_allowed(host) || is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn notify(target: &NotifyTarget, user: &str, notification: Notification<'_>) -> Result<()> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    let client = CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(NOTIFY_TIMEOUT)
+            .connect_timeout(NOTIFY_CONNECT_TIMEOUT)
+            .dns_resolver(PublicResolver)
+            // a redirect would skip the checks for IP addresses
+            .redirect(Policy::none())
+            .build()
+            .expect("Unable to build the notification client")
+    });
+
+    // IP addresses in the URL don't go through the resolver
+    check_target(target)
+        .await
+        .map_err(|err| anyhow!(err.message))?;
+
+    let request = match (target, notification) {
+        (NotifyTarget::Ntfy { url }, Notification::Grades(grades)) => {
+            let names: Vec<&str> = grades.iter().map(|grade| grade.name.as_str()).collect();
+            client
+                .post(url)
+                .header("Title", "Neue Noten in CampusDual")
+                .body(names.join(", "))
+        }
+        (NotifyTarget::Ntfy { url }, Notification::Disabled) => client
+            .post(url)
+            .header("Title", "Noten-Benachrichtigungen deaktiviert")
+            .body("Die Anmeldung bei CampusDual ist fehlgeschlagen (Passwort geändert?)"),
+        (NotifyTarget::Webhook { url }, Notification::Grades(grades)) => client
+            .post(url)
+            .json(&WebhookPayload::Grades { user, grades }),
+        (NotifyTarget::Webhook { url }, Notification::Disabled) => {
+            client.post(url).json(&WebhookPayload::Disabled { user })
+        }
+    };
+
+    request.send().await?.error_for_status()?;
+
+    Ok(())
+}
+
+fn load_store(store_path: &Path) -> Result<HashMap<String, WatchedUser>> {
+    if !store_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let store = decrypt_urlsafe(fs::read_to_string(store_path)?.trim())
+        .context("store is not encrypted with a configured AES key")?;
+
+    Ok(serde_json::from_str(&store)?)
+}
+
+fn save_store() -> Result<(), ResponseError> {
+    // no store before init, e.g. in tests
+    let Some(store_path) = STORE_PATH.get() else {
+        return Ok(());
+    };
+
+    let store = serde_json::to_string(&*WATCHED_USERS.lock().unwrap())?;
+    let store = encrypt_urlsafe(&store).map_err(|status_code| ResponseError {
+        message: "Internal Server Error".to_string(),
+        status_code,
+    })?;
+
+    write_atomically(store_path, store).map_err(|err| {
+        log::error!("Unable to write grade watcher store: {err}");
+        ResponseError {
+            message: "Internal Server Error".to_string(),
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    })
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashSet,
+    hash::{Hash, Hasher},
+};
+
+use axum::{
+    body::Body,
+    http::{Response, header::CONTENT_TYPE},
+    response::IntoResponse,
+};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use fnv::FnvHasher;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{
+    constants::CD_TIMEZONE,
+    types::{
+        CampusDualSignupOption, CampusDualVerfahrenOption, StundenplanItem, TimelineEvent,
+        TimelineEventKind,
+    },
+};
+
+// exams without an end time in CampusDual
+const DEFAULT_EXAM_DURATION_MIN: i64 = 90;
+// all-day deadlines start at 00:00, so this fires at 09:00 the day before
+const DEADLINE_ALARM_TRIGGER: &str = "-PT15H";
+
+// RFC 5545 says lines SHOULD NOT be longer than 75 octets
+const MAX_LINE_OCTETS: usize = 75;
+
+// Every DATE-TIME is written as local time in CD_TIMEZONE, so the calendar
+// ships the matching definition instead of relying on the client's tz database
+const VTIMEZONE_EUROPE_BERLIN: &str = "BEGIN:VTIMEZONE\r
+TZID:Europe/Berlin\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:+0100\r
+TZOFFSETTO:+0200\r
+TZNAME:CEST\r
+DTSTART:19700329T020000\r
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:+0200\r
+TZOFFSETTO:+0100\r
+TZNAME:CET\r
+DTSTART:19701025T030000\r
+RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r
+END:STANDARD\r
+END:VTIMEZONE\r
+";
+
+// text/calendar response body
+pub struct Ics(pub String);
+
+impl IntoResponse for Ics {
+    fn into_response(self) -> Response<Body> {
+        ([(CONTENT_TYPE, "text/calendar; charset=utf-8")], self.0).into_response()
+    }
+}
+
+pub enum IcsTime {
+    Local(DateTime<Tz>),
+    Date(NaiveDate),
+}
+
+pub struct IcsEvent {
+    pub uid: String,
+    pub summary: String,
+    pub start: IcsTime,
+    pub end: Option<IcsTime>,
+    pub location: Option<String>,
+    pub description: Option<String>,
+    pub alarm: Option<IcsAlarm>,
+}
+
+pub struct IcsAlarm {
+    // RFC 5545 duration relative to DTSTART, e.g. "-PT15H"
+    pub trigger: String,
+    pub description: String,
+}
+
+pub fn write_calendar(name: &str, events: &[IcsEvent]) -> String {
+    let dtstamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//CampusUnbloat//campus-api//DE");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape_text(name)));
+    push_line(&mut ics, &format!("X-WR-TIMEZONE:{}", CD_TIMEZONE.name()));
+    ics.push_str(VTIMEZONE_EUROPE_BERLIN);
+
+    for event in events {
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", event.uid));
+        push_line(&mut ics, &format!("DTSTAMP:{dtstamp}"));
+        push_line(&mut ics, &format!("DTSTART{}", format_time(&event.start)));
+        if let Some(end) = &event.end {
+            push_line(&mut ics, &format!("DTEND{}", format_time(end)));
+        }
+        push_line(
+            &mut ics,
+            &format!("SUMMARY:{}", escape_text(&event.summary)),
+        );
+        if let Some(location) = &event.location {
+            push_line(&mut ics, &format!("LOCATION:{}", escape_text(location)));
+        }
+        if let Some(description) = &event.description {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape_text(description)),
+            );
+        }
+        if let Some(alarm) = &event.alarm {
+            push_line(&mut ics, "BEGIN:VALARM");
+            push_line(&mut ics, "ACTION:DISPLAY");
+            push_line(&mut ics, &format!("TRIGGER:{}", alarm.trigger));
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape_text(&alarm.description)),
+            );
+            push_line(&mut ics, "END:VALARM");
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+// Builds a UID that stays the same as long as the hashed parts don't change
+pub fn stable_uid(parts: &[&str]) -> String {
+    let mut hasher = FnvHasher::default();
+    parts.hash(&mut hasher);
+
+    format!("{:016x}@campus-api", hasher.finish())
+}
+
+pub fn stundenplan_to_ics(items: &[StundenplanItem], user: &str) -> String {
+    let events: Vec<IcsEvent> = items
+        .iter()
+        .filter_map(|item| {
+            let start = DateTime::from_timestamp(item.start, 0)?.with_timezone(&CD_TIMEZONE);
+            let end = DateTime::from_timestamp(item.end, 0)?.with_timezone(&CD_TIMEZONE);
+
+            let (start, end) = if item.all_day {
+                (
+                    IcsTime::Date(start.date_naive()),
+                    end.date_naive().succ_opt().map(IcsTime::Date),
+                )
+            } else {
+                (IcsTime::Local(start), Some(IcsTime::Local(end)))
+            };
+
+            let location = [&item.room, &item.sroom]
+                .into_iter()
+                .find(|room| !room.trim().is_empty())
+                .map(|room| room.trim().to_string());
+
+            let mut description = Vec::new();
+            if !item.description.trim().is_empty() && item.description != item.title {
+                description.push(item.description.trim().to_string());
+            }
+            if !item.instructor.trim().is_empty() {
+                description.push(format!("Dozent: {}", item.instructor.trim()));
+            }
+            if !item.remarks.trim().is_empty() {
+                description.push(format!("Bemerkung: {}", item.remarks.trim()));
+            }
+
+            Some(IcsEvent {
+                uid: stable_uid(&[
+                    "stundenplan",
+                    user,
+                    &item.title,
+                    &item.start.to_string(),
+                    &item.end.to_string(),
+                    &item.room,
+                ]),
+                summary: item.title.clone(),
+                start,
+                end,
+                location,
+                description: (!description.is_empty()).then(|| description.join("\n")),
+                alarm: None,
+            })
+        })
+        .collect();
+
+    write_calendar("Stundenplan", &events)
+}
+
+pub fn exams_to_ics(
+    signup_options: &[CampusDualSignupOption],
+    signup_verfahren: &[CampusDualVerfahrenOption],
+    user: &str,
+) -> String {
+    let mut events: Vec<IcsEvent> = Vec::new();
+
+    for option in signup_options {
+        events.extend(exam_event(
+            user,
+            &option.name,
+            [
+                &option.verfahren,
+                &option.pruefart,
+                &option.signup_information,
+            ],
+            option.exam_date.as_deref(),
+            option.exam_time.as_deref(),
+            option.exam_room.as_deref(),
+        ));
+
+        // 🚫 means the signup period is already over
+        if option.status != "🚫" {
+            events.extend(deadline_event(
+                user,
+                "Anmeldeschluss",
+                &option.name,
+                option.signup_until.as_deref(),
+                option.warning_message.as_deref(),
+            ));
+        }
+    }
+
+    for option in signup_verfahren {
+        events.extend(exam_event(
+            user,
+            &option.name,
+            [
+                &option.verfahren,
+                &option.pruefart,
+                &option.signup_information,
+            ],
+            option.exam_date.as_deref(),
+            option.exam_time.as_deref(),
+            option.exam_room.as_deref(),
+        ));
+        events.extend(deadline_event(
+            user,
+            "Abmeldeschluss",
+            &option.name,
+            option.signoff_until.as_deref(),
+            option.warning_message.as_deref(),
+        ));
+    }
+
+    // registered exams can show up on both pages
+    let mut seen_uids = HashSet::new();
+    events.retain(|event| seen_uids.insert(event.uid.clone()));
+
+    write_calendar("Prüfungen", &events)
+}
+
+pub fn timeline_to_ics(timeline_events: &[TimelineEvent], user: &str) -> String {
+    let events: Vec<IcsEvent> = timeline_events
+        .iter()
+        .filter_map(|event| {
+            // semester phases span whole days, so export them as all-day events
+            let start = event.start?.with_timezone(&CD_TIMEZONE).date_naive();
+            let end = event
+                .end
+                .map(|end| end.with_timezone(&CD_TIMEZONE).date_naive())
+                .filter(|end| *end >= start)
+                .unwrap_or(start);
+
+            let summary = match event.kind {
+                TimelineEventKind::Theoriesemester => format!("Theorie: {}", event.name),
+                TimelineEventKind::Praxissemester => format!("Praxis: {}", event.name),
+                _ => event.name.clone(),
+            };
+
+            Some(IcsEvent {
+                uid: stable_uid(&[
+                    "timeline",
+                    user,
+                    &event.name,
+                    &start.to_string(),
+                    &end.to_string(),
+                ]),
+                summary,
+                start: IcsTime::Date(start),
+                end: end.succ_opt().map(IcsTime::Date),
+                location: None,
+                description: Some(event.description.trim().to_string())
+                    .filter(|description| !description.is_empty()),
+                alarm: None,
+            })
+        })
+        .collect();
+
+    write_calendar("Studienverlauf", &events)
+}
+
+fn exam_event(
+    user: &str,
+    name: &str,
+    details: [&String; 3],
+    exam_date: Option<&str>,
+    exam_time: Option<&str>,
+    exam_room: Option<&str>,
+) -> Option<IcsEvent> {
+    lazy_static! {
+        static ref RE_TIME: Regex = Regex::new(r"(\d{1,2}):(\d{2})").unwrap();
+    }
+
+    let date = parse_cd_date(exam_date?)?;
+
+    let times: Vec<NaiveTime> = exam_time
+        .map(|time| {
+            RE_TIME
+                .captures_iter(time)
+                .filter_map(|caps| {
+                    NaiveTime::from_hms_opt(caps[1].parse().ok()?, caps[2].parse().ok()?, 0)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let (start, end) = match times.first() {
+        Some(start_time) => {
+            let start = date
+                .and_time(*start_time)
+                .and_local_timezone(CD_TIMEZONE)
+                .earliest()?;
+            let end = times
+                .get(1)
+                .and_then(|end_time| {
+                    date.and_time(*end_time)
+                        .and_local_timezone(CD_TIMEZONE)
+                        .earliest()
+                })
+                .filter(|end| *end > start)
+                .unwrap_or(start + TimeDelta::minutes(DEFAULT_EXAM_DURATION_MIN));
+            (IcsTime::Local(start), Some(IcsTime::Local(end)))
+        }
+        None => (IcsTime::Date(date), date.succ_opt().map(IcsTime::Date)),
+    };
+
+    let description: Vec<&str> = details
+        .iter()
+        .map(|detail| detail.trim())
+        .filter(|detail| !detail.is_empty())
+        .collect();
+
+    Some(IcsEvent {
+        uid: stable_uid(&["exam", user, name, exam_date?]),
+        summary: format!("Prüfung: {name}"),
+        start,
+        end,
+        location: exam_room
+            .map(|room| room.trim().to_string())
+            .filter(|room| !room.is_empty()),
+        description: (!description.is_empty()).then(|| description.join("\n")),
+        alarm: None,
+    })
+}
+
+fn deadline_event(
+    user: &str,
+    kind: &str,
+    name: &str,
+    until: Option<&str>,
+    warning_message: Option<&str>,
+) -> Option<IcsEvent> {
+    let date = parse_cd_date(until?)?;
+
+    Some(IcsEvent {
+        uid: stable_uid(&["deadline", user, kind, name, until?]),
+        summary: format!("{kind}: {name}"),
+        start: IcsTime::Date(date),
+        end: date.succ_opt().map(IcsTime::Date),
+        location: None,
+        description: warning_message.map(|msg| msg.trim().to_string()),
+        alarm: Some(IcsAlarm {
+            trigger: DEADLINE_ALARM_TRIGGER.to_string(),
+            description: format!("{kind} morgen: {name}"),
+        }),
+    })
+}
+
+fn parse_cd_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.trim(), "%d.%m.%Y").ok()
+}
+
+fn format_time(time: &IcsTime) -> String {
+    match time {
+        IcsTime::Local(datetime) => format!(
+            ";TZID={}:{}",
+            datetime.timezone().name(),
+            datetime.format("%Y%m%dT%H%M%S")
+        ),
+        IcsTime::Date(date) => format!(";VALUE=DATE:{}", date.format("%Y%m%d")),
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Folds content lines longer than MAX_LINE_OCTETS without splitting UTF-8 sequences
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for ch in line.chars() {
+        if octets + ch.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            // the leading space of the continuation counts towards the limit
+            octets = 1;
+        }
+        octets += ch.len_utf8();
+        ics.push(ch);
+    }
+    ics.push_str("\r\n");
+}
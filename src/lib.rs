@@ -0,0 +1,28 @@
+//! CampusDual client, scrapers and types behind the Campus API server.
+//!
+//! Log in with [`campus_backend::client::CampusDualClient::login`], then call the
+//! [`campus_backend::client::CampusDual`] trait methods on the returned client. A saved
+//! [`types::CdAuthData`] becomes a client again with `CampusDualClient::from`. The
+//! CampusDual origins default to the public instance, [`constants::set_statics_from_env`]
+//! is only needed for the server.
+
+mod atomic_write;
+mod auth;
+pub mod campus_backend;
+mod color_stuff;
+pub mod constants;
+mod encryption;
+mod feed;
+mod gpa;
+mod grade_changes;
+#[cfg(feature = "grade-watcher")]
+pub mod grade_watcher;
+pub mod ical;
+mod ratelimit_keyextractor;
+pub mod revocation;
+pub mod routes;
+mod services;
+#[cfg(test)]
+mod tests;
+pub mod types;
+pub mod vault;
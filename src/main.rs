@@ -1,21 +1,14 @@
 use std::env;
 
-use constants::{
-    CD_CERT_PEM, LOGIN_RATELIMIT_QUOTA, LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC, RATELIMIT_QUOTA,
-    RATELIMIT_RESTORE_INTERVAL_SEC, set_statics_from_env,
+use campus_api::{
+    constants::{
+        LOGIN_RATELIMIT_QUOTA, LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC, RATELIMIT_QUOTA,
+        RATELIMIT_RESTORE_INTERVAL_SEC, set_statics_from_env,
+    },
+    routes,
 };
 use tokio::net::TcpListener;
 
-mod auth;
-pub mod campus_backend;
-mod color_stuff;
-mod constants;
-mod encryption;
-mod ratelimit_keyextractor;
-mod routes;
-mod services;
-mod types;
-
 #[tokio::main]
 async fn main() {
     if env::var(pretty_env_logger::env_logger::DEFAULT_FILTER_ENV).is_err() {
@@ -28,6 +21,9 @@ async fn main() {
 
     set_statics_from_env();
 
+    #[cfg(feature = "grade-watcher")]
+    campus_api::grade_watcher::init();
+
     log::info!("Starting Campus API...");
     log::info!("Rate limit: {}", RATELIMIT_QUOTA.get().unwrap());
     log::info!(
@@ -40,16 +36,15 @@ async fn main() {
         LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC.get().unwrap()
     );
 
-    let buf = include_bytes!("GEANT TLS RSA 1.pem");
-    let cert = reqwest::Certificate::from_pem(buf).unwrap();
-    CD_CERT_PEM.set(cert).unwrap();
-
     let listener = TcpListener::bind("0.0.0.0:8080")
         .await
         .expect("Unable to start the server");
 
     log::info!("Listening on {}", listener.local_addr().unwrap());
 
+    #[cfg(feature = "grade-watcher")]
+    tokio::spawn(campus_api::grade_watcher::run());
+
     let app = routes::app().await;
 
     axum::serve(listener, app)
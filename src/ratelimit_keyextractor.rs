@@ -13,6 +13,9 @@ pub struct GovJwtExtractorHashed;
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct GovIpOrGlobalExtractorHashed;
 
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
+pub struct GovFeedTokenExtractorHashed;
+
 impl KeyExtractor for GovJwtExtractorHashed {
     type Key = String;
 
@@ -42,3 +45,21 @@ impl KeyExtractor for GovIpOrGlobalExtractorHashed {
         }
     }
 }
+
+impl KeyExtractor for GovFeedTokenExtractorHashed {
+    type Key = String;
+
+    // feed tokens are part of the path, e.g. /feed/{token}/stundenplan.ics
+    fn extract<B>(&self, req: &Request<B>) -> Result<Self::Key, GovernorError> {
+        req.uri()
+            .path()
+            .strip_prefix("/feed/")
+            .and_then(|path| path.split('/').next())
+            .map(|token| token.to_string())
+            .ok_or(GovernorError::Other {
+                code: StatusCode::TOO_MANY_REQUESTS,
+                msg: Some("".to_string()),
+                headers: None,
+            })
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+
+use crate::atomic_write::write_atomically;
+
+// Revoked token (jti) and feed IDs, each with the unix timestamp after which
+// it doesn't need to be remembered anymore
+pub trait RevocationStore: Send + Sync {
+    fn revoke(&self, id: &str, expires_at: usize) -> Result<()>;
+
+    fn is_revoked(&self, id: &str) -> bool;
+}
+
+// Default store, forgets everything on restart
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+    revoked: Mutex<HashMap<String, usize>>,
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn revoke(&self, id: &str, expires_at: usize) -> Result<()> {
+        let now = Utc::now().timestamp() as usize;
+
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        revoked.insert(id.to_string(), expires_at);
+
+        Ok(())
+    }
+
+    fn is_revoked(&self, id: &str) -> bool {
+        self.revoked.lock().unwrap().contains_key(id)
+    }
+}
+
+// In-memory store that appends every revocation to a file ("id expires_at" per line),
+// expired lines are dropped when the file is opened again
+pub struct FileRevocationStore {
+    path: PathBuf,
+    memory: MemoryRevocationStore,
+}
+
+impl FileRevocationStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let now = Utc::now().timestamp() as usize;
+
+        let mut revoked = HashMap::new();
+        if path.exists() {
+            for line in fs::read_to_string(&path)?.lines() {
+                let (id, expires_at) =
+                    line.split_once(' ').context("malformed revocation entry")?;
+                let expires_at: usize = expires_at.parse()?;
+
+                if expires_at > now {
+                    revoked.insert(id.to_string(), expires_at);
+                }
+            }
+        }
+
+        let compacted: String = revoked
+            .iter()
+            .map(|(id, expires_at)| format!("{id} {expires_at}\n"))
+            .collect();
+        write_atomically(&path, compacted)?;
+
+        Ok(FileRevocationStore {
+            path,
+            memory: MemoryRevocationStore {
+                revoked: Mutex::new(revoked),
+            },
+        })
+    }
+}
+
+impl RevocationStore for FileRevocationStore {
+    fn revoke(&self, id: &str, expires_at: usize) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{id} {expires_at}")?;
+
+        self.memory.revoke(id, expires_at)
+    }
+
+    fn is_revoked(&self, id: &str) -> bool {
+        self.memory.is_revoked(id)
+    }
+}
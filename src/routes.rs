@@ -11,11 +11,15 @@ use tower_http::cors::{Any, CorsLayer};
 
 use crate::{
     auth,
+    campus_backend::client::CampusDualClient,
     constants::{
         LOGIN_RATELIMIT_QUOTA, LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC, RATELIMIT_QUOTA,
         RATELIMIT_RESTORE_INTERVAL_SEC,
     },
-    ratelimit_keyextractor::{GovIpOrGlobalExtractorHashed, GovJwtExtractorHashed},
+    feed, gpa, grade_changes,
+    ratelimit_keyextractor::{
+        GovFeedTokenExtractorHashed, GovIpOrGlobalExtractorHashed, GovJwtExtractorHashed,
+    },
     services,
 };
 
@@ -42,8 +46,18 @@ pub async fn app() -> Router {
             .unwrap(),
     );
 
+    let governor_conf_feed = Arc::new(
+        GovernorConfigBuilder::default()
+            .burst_size(*RATELIMIT_QUOTA.get().unwrap())
+            .per_second(*RATELIMIT_RESTORE_INTERVAL_SEC.get().unwrap())
+            .key_extractor(GovFeedTokenExtractorHashed)
+            .finish()
+            .unwrap(),
+    );
+
     let governor_limiter_jwt = governor_conf_jwt.limiter().clone();
     let governor_limiter_signin = governor_conf_signin.limiter().clone();
+    let governor_limiter_feed = governor_conf_feed.limiter().clone();
 
     // a separate background task to clean up
     let interval = Duration::from_secs(60);
@@ -52,6 +66,7 @@ pub async fn app() -> Router {
             std::thread::sleep(interval);
             governor_limiter_jwt.retain_recent();
             governor_limiter_signin.retain_recent();
+            governor_limiter_feed.retain_recent();
         }
     });
 
@@ -62,29 +77,128 @@ pub async fn app() -> Router {
         .allow_origin(Any)
         .allow_headers([CONTENT_TYPE]);
 
+    // calendar clients can't send headers, the feed token in the path is the auth
+    let feed_routes = Router::new()
+        .route(
+            "/feed/{token}/stundenplan.ics",
+            get(feed::get_feed_stundenplan),
+        )
+        .route("/feed/{token}/exams.ics", get(feed::get_feed_exams))
+        .route("/feed/{token}/timeline.ics", get(feed::get_feed_timeline))
+        .layer(GovernorLayer::new(governor_conf_feed));
+
     Router::new()
-        .route("/check_revive_session", get(services::check_revive_session))
-        .route("/get_grades", get(services::get_grades))
-        .route("/get_gradestats", post(services::get_gradestats))
-        .route("/get_examsignup", get(services::get_examsignup))
-        .route("/registerexam", post(services::post_registerexam))
-        .route("/get_examdetails", post(services::get_examdetails))
-        .route("/cancelexam", post(services::post_cancelexam))
-        .route("/get_examverfahren", get(services::get_examverfahren))
-        .route("/get_ects", get(services::get_ects))
-        .route("/get_fachsem", get(services::get_fachsem))
-        .route("/get_examstats", get(services::get_examstats))
-        .route("/get_stundenplan", get(services::get_stundenplan))
-        .route("/get_reminders", get(services::get_reminders))
-        .route("/get_timeline", get(services::get_timeline))
+        .route(
+            "/check_revive_session",
+            get(services::check_revive_session::<CampusDualClient>),
+        )
+        .route("/get_grades", get(services::get_grades::<CampusDualClient>))
+        .route(
+            "/get_gradestats",
+            post(services::get_gradestats::<CampusDualClient>),
+        )
+        .route(
+            "/get_examsignup",
+            get(services::get_examsignup::<CampusDualClient>),
+        )
+        .route(
+            "/registerexam",
+            post(services::post_registerexam::<CampusDualClient>),
+        )
+        .route(
+            "/get_examdetails",
+            post(services::get_examdetails::<CampusDualClient>),
+        )
+        .route(
+            "/cancelexam",
+            post(services::post_cancelexam::<CampusDualClient>),
+        )
+        .route(
+            "/get_examverfahren",
+            get(services::get_examverfahren::<CampusDualClient>),
+        )
+        .route(
+            "/get_exams.ics",
+            get(services::get_exams_ics::<CampusDualClient>),
+        )
+        .route("/get_ects", get(services::get_ects::<CampusDualClient>))
+        .route(
+            "/get_fachsem",
+            get(services::get_fachsem::<CampusDualClient>),
+        )
+        .route(
+            "/get_examstats",
+            get(services::get_examstats::<CampusDualClient>),
+        )
+        .route(
+            "/get_stundenplan",
+            get(services::get_stundenplan::<CampusDualClient>),
+        )
+        .route(
+            "/get_stundenplan.ics",
+            get(services::get_stundenplan_ics::<CampusDualClient>),
+        )
+        .route(
+            "/get_reminders",
+            get(services::get_reminders::<CampusDualClient>),
+        )
+        .route(
+            "/get_timeline",
+            get(services::get_timeline::<CampusDualClient>),
+        )
+        .route(
+            "/get_timeline_events",
+            get(services::get_timeline_events::<CampusDualClient>),
+        )
+        .route(
+            "/get_timeline.ics",
+            get(services::get_timeline_ics::<CampusDualClient>),
+        )
+        .route(
+            "/get_grade_changes",
+            get(grade_changes::get_grade_changes::<CampusDualClient>),
+        )
+        .route(
+            "/get_gpa",
+            get(gpa::get_gpa::<CampusDualClient>).post(gpa::post_gpa_what_if::<CampusDualClient>),
+        )
+        .route(
+            "/dashboard",
+            get(services::get_dashboard::<CampusDualClient>),
+        )
+        .route("/feed/create", post(feed::create_feed))
+        .route("/feed/revoke", post(feed::revoke_feed))
+        .route("/signout", post(auth::signout))
+        .merge(grade_watcher_routes())
         // apply auth and jwt rate limiting to all previous (jwt is only stored as hash)
-        .layer(GovernorLayer::new(governor_conf_jwt))
+        .layer(GovernorLayer::new(governor_conf_jwt.clone()))
         .layer(middleware::from_fn(auth::authorize))
+        // takes the refresh token instead of an access token, same rate limit
+        .route(
+            "/token/refresh",
+            post(auth::refresh_token).layer(GovernorLayer::new(governor_conf_jwt)),
+        )
         // sign in rate limiting (based on username, only stored as hash)
         .route(
             "/signin",
             post(auth::sign_in).layer(GovernorLayer::new(governor_conf_signin)),
         )
+        .merge(feed_routes)
+        .route("/.well-known/jwks.json", get(auth::jwks))
         .route("/", get(|| async { "API is reachable".into_response() }))
         .layer(cors)
 }
+
+// opt-in grade watcher endpoints, nothing without the grade-watcher feature
+fn grade_watcher_routes() -> Router {
+    #[cfg(feature = "grade-watcher")]
+    return Router::new()
+        .route("/watcher/subscribe", post(crate::grade_watcher::subscribe))
+        .route(
+            "/watcher/unsubscribe",
+            post(crate::grade_watcher::unsubscribe),
+        );
+
+    #[cfg(not(feature = "grade-watcher"))]
+    Router::new()
+}
@@ -1,59 +1,38 @@
-use axum::{Extension, Json};
-use chrono::DateTime;
+use axum::{Extension, Json, extract::Query};
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, NaiveTime, Utc, Weekday};
 use fnv::FnvHasher;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode};
 use std::hash::{Hash, Hasher};
 
 use crate::{
-    auth::sign_in,
-    campus_backend::req_client_funcs::{
-        extract_exam_signup_options, extract_exam_verfahren_options, extract_grades,
-        get_client_default, get_client_with_cd_cookie,
-    },
+    auth::{revive_password, sign_in_session},
+    campus_backend::client::CampusDual,
     color_stuff::hex_to_luminance,
+    constants::{CD_TIMEZONE, STUNDENPLAN_MAX_SPAN_DAYS, required_credit_points},
+    ical::{Ics, exams_to_ics, stundenplan_to_ics, timeline_to_ics},
     types::{
-        CampusDualGrade, CampusDualSignupOption, CampusDualVerfahrenOption, CampusLoginData,
-        CampusReminders, CampusTimeline, CampusTimelineEvent, CdAuthData, CdExamDetails,
-        CdExamStats, CdGradeStatEntry, ExamRegistrationMetadata, ExportTimelineEvent,
-        ExportTimelineEvents, GradeStatsAllStudents, LoginResponse, ResponseError, StundenplanItem,
-        SubGradeMetadata,
+        CampusDualGrade, CampusDualSignupOption, CampusDualVerfahrenOptions, CampusLoginData,
+        CdAuthData, CdExamDetails, CdExamStats, CreditPoints, Dashboard, ExamRegistrationMetadata,
+        ExportTimelineEvent, ExportTimelineEvents, Fachsemester, GradeStatsAllStudents,
+        GradeStatsRequest, LatestGradeReminder, LoginResponse, Reminders, ResponseError,
+        SealedPassword, StundenplanItem, StundenplanQuery, TimelineEvent, TimelineEventKind,
+        TokenId, UpcomingEventReminder,
     },
 };
 
-pub async fn get_grades(
-    Extension(cd_auth_data): Extension<CdAuthData>,
+pub async fn get_grades<C: CampusDual>(
+    Extension(client): Extension<C>,
 ) -> Result<Json<Vec<CampusDualGrade>>, ResponseError> {
-    let client = get_client_with_cd_cookie(true, cd_auth_data.cookie)?;
-
-    let grade_html = client
-        .get("https://selfservice.campus-dual.de/acwork/index")
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-
-    let grades = extract_grades(grade_html)?;
+    let grades = client.grades().await?;
 
     Ok(Json(grades))
 }
 
-pub async fn get_gradestats(
-    Extension(cd_auth_data): Extension<CdAuthData>,
-    Json(subgrade_meta): Json<SubGradeMetadata>,
+pub async fn get_gradestats<C: CampusDual>(
+    Extension(client): Extension<C>,
+    Json(request): Json<GradeStatsRequest>,
 ) -> Result<Json<GradeStatsAllStudents>, ResponseError> {
-    let client = get_client_with_cd_cookie(true, cd_auth_data.cookie)?;
-
-    let grade_stats: Vec<CdGradeStatEntry> = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/acwork/mscoredist?module={}&peryr={}&perid={}",
-            subgrade_meta.module, subgrade_meta.peryr, subgrade_meta.perid
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let grade_stats = client.grade_distribution(&request.meta).await?;
 
     let mut all_stats = GradeStatsAllStudents::default();
 
@@ -65,246 +44,207 @@ pub async fn get_gradestats(
             "ausreichend" => all_stats.four = stat.count,
             "nicht ausreichend" => all_stats.ronmodus = stat.count,
             _ => {
-                log::warn!("unknown grade stat key: {}", stat.gradetext)
+                *all_stats.other.entry(stat.gradetext).or_default() += stat.count;
             }
         }
     }
 
+    // counts of 1.0 to 5.0
+    let counts = [
+        all_stats.one,
+        all_stats.two,
+        all_stats.three,
+        all_stats.four,
+        all_stats.ronmodus,
+    ];
+    let total: i64 = counts.iter().sum();
+
+    if total > 0 {
+        let nth_grade = |n: i64| {
+            let mut seen = 0;
+            counts
+                .iter()
+                .position(|&count| {
+                    seen += count;
+                    seen > n
+                })
+                .map_or(5.0, |bucket| bucket as f32 + 1.0)
+        };
+        let weighted_sum: f32 = counts
+            .iter()
+            .enumerate()
+            .map(|(bucket, &count)| (bucket as f32 + 1.0) * count as f32)
+            .sum();
+
+        all_stats.mean = Some(round2(weighted_sum / total as f32));
+        all_stats.median = Some((nth_grade((total - 1) / 2) + nth_grade(total / 2)) / 2.0);
+        all_stats.pass_rate = Some(round2(
+            100.0 * (total - all_stats.ronmodus) as f32 / total as f32,
+        ));
+        all_stats.percentile_rank = request.grade.map(|grade| {
+            let bucket = grade_bucket(grade);
+            let worse: i64 = counts[bucket + 1..].iter().sum();
+            round2(100.0 * (worse as f32 + counts[bucket] as f32 / 2.0) / total as f32)
+        });
+    }
+
     Ok(Json(all_stats))
 }
 
-pub async fn check_revive_session(
+// index into one..ronmodus, the grade texts cover these ranges of the German scale
+fn grade_bucket(grade: f32) -> usize {
+    match grade {
+        ..=1.5 => 0,
+        ..=2.5 => 1,
+        ..=3.5 => 2,
+        ..=4.0 => 3,
+        _ => 4,
+    }
+}
+
+fn round2(value: f32) -> f32 {
+    (value * 100.0).round() / 100.0
+}
+
+pub async fn check_revive_session<C: CampusDual>(
+    Extension(client): Extension<C>,
     Extension(cd_auth_data): Extension<CdAuthData>,
+    Extension(sealed_password): Extension<SealedPassword>,
+    Extension(token_id): Extension<TokenId>,
+    headers: HeaderMap,
 ) -> Result<Json<Option<LoginResponse>>, ResponseError> {
-    let client = get_client_with_cd_cookie(false, cd_auth_data.cookie)?;
-
-    let resp = client
-        .get("https://erp.campus-dual.de/sap/bc/webdynpro/sap/zba_initss?sap-client=100&sap-language=de&uri=https://selfservice.campus-dual.de/index/login")
-        .send()
-        .await?;
-
-    match resp.status().as_u16() {
-        // 200 means the old session is not alive anymore
-        200 => {
-            let new_login_response = sign_in(Json(CampusLoginData {
-                username: cd_auth_data.user,
-                password: cd_auth_data.password,
-            }))
-            .await;
-
-            log::info!("CaDu auth revive ok?={}", new_login_response.is_ok());
-
-            match new_login_response {
-                Ok(Json(login_response)) => Ok(Json(Some(login_response))),
-                Err(_) => Err(ResponseError {
-                    message: "Failed to log in to CaDu - did the password change?".to_string(),
-                    status_code: StatusCode::UNAUTHORIZED,
-                }),
-            }
-        }
-        500 => Ok(Json(None)),
-        _ => Err(ResponseError {
-            message: "CD healthcheck failed".to_string(),
-            status_code: resp.status(),
+    // checked even for live sessions, so a client that lost its secret finds out early
+    let password = revive_password(&headers, &sealed_password)?;
+
+    let session_alive = client.session_alive().await.map_err(|_| ResponseError {
+        message: "CD healthcheck failed".to_string(),
+        status_code: StatusCode::BAD_GATEWAY,
+    })?;
+
+    if session_alive {
+        return Ok(Json(None));
+    }
+
+    let new_login_response = sign_in_session(
+        CampusLoginData {
+            username: cd_auth_data.user,
+            password,
+        },
+        token_id.0,
+    )
+    .await;
+
+    log::info!("CaDu auth revive ok?={}", new_login_response.is_ok());
+
+    match new_login_response {
+        Ok(Json(login_response)) => Ok(Json(Some(login_response))),
+        Err(_) => Err(ResponseError {
+            message: "Failed to log in to CaDu - did the password change?".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
         }),
     }
 }
 
-pub async fn get_examsignup(
-    Extension(cd_auth_data): Extension<CdAuthData>,
+pub async fn get_examsignup<C: CampusDual>(
+    Extension(client): Extension<C>,
 ) -> Result<Json<Vec<CampusDualSignupOption>>, ResponseError> {
-    let client = get_client_with_cd_cookie(true, cd_auth_data.cookie)?;
-    let exam_signup_html = client
-        .get("https://selfservice.campus-dual.de/acwork/expproc")
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-
-    let signup_options = extract_exam_signup_options(exam_signup_html).await?;
+    let signup_options = client.exam_signups().await?;
 
     Ok(Json(signup_options))
 }
 
-pub async fn post_registerexam(
-    Extension(cd_auth_data): Extension<CdAuthData>,
+pub async fn post_registerexam<C: CampusDual>(
+    Extension(client): Extension<C>,
     Json(examregist_meta): Json<ExamRegistrationMetadata>,
 ) -> Result<String, ResponseError> {
-    let client = get_client_default(true)?;
-    let exam_regist_resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/acwork/registerexam?userid={}&assessment={}&peryr={}&perid={}&offerno={}&hash={}",
-            cd_auth_data.user,
-            examregist_meta.assessment,
-            examregist_meta.peryr,
-            examregist_meta.perid,
-            examregist_meta.offerno,
-            cd_auth_data.hash,
-        ))
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(exam_regist_resp.text().await?)
+    Ok(client.register_exam(&examregist_meta).await?)
 }
 
-pub async fn get_examdetails(
-    Extension(cd_auth_data): Extension<CdAuthData>,
+pub async fn get_examdetails<C: CampusDual>(
+    Extension(client): Extension<C>,
     Json(examregist_meta): Json<ExamRegistrationMetadata>,
 ) -> Result<Json<CdExamDetails>, ResponseError> {
-    let client = get_client_default(true)?;
-    let mut exam_details: CdExamDetails = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/acwork/offerdetail?user={}&objidexm=undefined&evob_objid={}&peryr={}&perid={}&offerno={}",
-            cd_auth_data.user,
-            examregist_meta.assessment,
-            examregist_meta.peryr,
-            examregist_meta.perid,
-            examregist_meta.offerno,
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
-
-    let examorg_long = {
-        let resp = client
-            .get(format!(
-                "https://selfservice.campus-dual.de/acwork/examorg?examorg={}",
-                exam_details.ev_examorg_text
-            ))
-            .send()
-            .await?
-            .error_for_status();
-        match resp {
-            Ok(resp) => serde_json::from_str(&resp.text().await?)?,
-            Err(_) => exam_details.ev_examorg_text.clone(),
-        }
-    };
+    let exam_details = client.exam_details(&examregist_meta).await?;
 
-    exam_details.ev_examorg_longtext = Some(examorg_long);
     Ok(Json(exam_details))
 }
 
-pub async fn post_cancelexam(
-    Extension(cd_auth_data): Extension<CdAuthData>,
+pub async fn post_cancelexam<C: CampusDual>(
+    Extension(client): Extension<C>,
     Json(examregist_meta): Json<ExamRegistrationMetadata>,
 ) -> Result<String, ResponseError> {
-    let client = get_client_default(true)?;
-    let exam_regist_resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/acwork/cancelexam?userid={}&objid={}&hash={}",
-            cd_auth_data.user, examregist_meta.assessment, cd_auth_data.hash
-        ))
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(exam_regist_resp.text().await?)
+    Ok(client.cancel_exam(&examregist_meta).await?)
 }
 
-pub async fn get_examverfahren(
-    Extension(cd_auth_data): Extension<CdAuthData>,
-) -> Result<Json<Vec<CampusDualVerfahrenOption>>, ResponseError> {
-    let client = get_client_with_cd_cookie(true, cd_auth_data.cookie)?;
-    let exam_verfahren_html = client
-        .get("https://selfservice.campus-dual.de/acwork/cancelproc")
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-
-    let signup_verfahren = extract_exam_verfahren_options(exam_verfahren_html).await?;
+pub async fn get_examverfahren<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<CampusDualVerfahrenOptions>, ResponseError> {
+    let signup_verfahren = client.exam_verfahren().await?;
 
     Ok(Json(signup_verfahren))
 }
 
-pub async fn get_ects(
-    Extension(cd_authdata): Extension<CdAuthData>,
-) -> Result<String, ResponseError> {
-    let client = get_client_default(true)?;
+pub async fn get_exams_ics<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Ics, ResponseError> {
+    Ok(Ics(fetch_exams_ics(&client).await?))
+}
 
-    let user = cd_authdata.user;
-    let hash = cd_authdata.hash;
+// shared with the exams feed
+pub async fn fetch_exams_ics<C: CampusDual>(client: &C) -> Result<String, ResponseError> {
+    let (signup_options, signup_verfahren) =
+        tokio::try_join!(client.exam_signups(), client.exam_verfahren())?;
 
-    let resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/dash/getcp?user={user}&hash={hash}"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
-    Ok(resp)
+    Ok(exams_to_ics(
+        &signup_options,
+        &signup_verfahren.options,
+        client.user(),
+    ))
 }
 
-pub async fn get_fachsem(
-    Extension(cd_authdata): Extension<CdAuthData>,
-) -> Result<String, ResponseError> {
-    let client = get_client_default(true)?;
+pub async fn get_ects<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<CreditPoints>, ResponseError> {
+    let earned = parse_cd_number(&client.ects().await?, "credit point count")?;
 
-    let user = cd_authdata.user;
-    let hash = cd_authdata.hash;
+    Ok(Json(CreditPoints {
+        earned,
+        required: required_credit_points(),
+    }))
+}
 
-    let resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/dash/getfs?user={user}&hash={hash}"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?;
+pub async fn get_fachsem<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<Fachsemester>, ResponseError> {
+    let semester = parse_cd_number(&client.fachsem().await?, "semester")?;
 
-    // Remove the quotes from the string, parse number
-    let whyisthisnecessary = resp.replace('"', "");
-    let number = whyisthisnecessary.trim().parse::<u32>().unwrap_or(1);
+    Ok(Json(Fachsemester { semester }))
+}
 
-    Ok(number.to_string())
+// dash/getcp and dash/getfs answer with a number in quotes, e.g. "4"
+fn parse_cd_number(body: &str, what: &str) -> Result<u32, ResponseError> {
+    body.trim()
+        .trim_matches('"')
+        .trim()
+        .parse()
+        .map_err(|_| ResponseError {
+            message: format!("CampusDual returned an invalid {what}"),
+            status_code: StatusCode::BAD_GATEWAY,
+        })
 }
 
-pub async fn get_examstats(
-    Extension(cd_authdata): Extension<CdAuthData>,
+pub async fn get_examstats<C: CampusDual>(
+    Extension(client): Extension<C>,
 ) -> Result<Json<CdExamStats>, ResponseError> {
-    let client = get_client_default(true)?;
-
-    let user = cd_authdata.user;
-    let hash = cd_authdata.hash;
-
-    let resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/dash/getexamstats?user={user}&hash={hash}"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<CdExamStats>()
-        .await?;
-
-    Ok(Json(resp))
+    Ok(Json(client.exam_stats().await?))
 }
 
-pub async fn get_stundenplan(
-    Extension(cd_authdata): Extension<CdAuthData>,
+pub async fn get_stundenplan<C: CampusDual>(
+    Extension(client): Extension<C>,
+    Query(query): Query<StundenplanQuery>,
 ) -> Result<Json<Vec<StundenplanItem>>, ResponseError> {
-    let client = get_client_default(true)?;
-
-    let user = cd_authdata.user;
-    let hash = cd_authdata.hash;
-
-    let mut stundenplan: Vec<StundenplanItem> = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/room/json?userid={user}&hash={hash}&start=1720735200&end=1720821600"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let (start, end) = stundenplan_range(&query)?;
+    let mut stundenplan = client.timetable(start, end).await?;
 
     for item in &mut stundenplan {
         item.start *= 1000;
@@ -326,71 +266,99 @@ pub async fn get_stundenplan(
     Ok(Json(stundenplan))
 }
 
-fn string_to_rgb(input: &str) -> String {
-    // Create a hasher
-    let mut hasher = FnvHasher::default();
+pub async fn get_stundenplan_ics<C: CampusDual>(
+    Extension(client): Extension<C>,
+    Query(query): Query<StundenplanQuery>,
+) -> Result<Ics, ResponseError> {
+    let (start, end) = stundenplan_range(&query)?;
+    let stundenplan = client.timetable(start, end).await?;
 
-    // Hash the input string
-    input.hash(&mut hasher);
-    let hash = hasher.finish();
-
-    // Extract RGB components from the hash
-    let r = (hash & 0xFF) as u8;
-    let g = ((hash >> 8) & 0xFF) as u8;
-    let b = ((hash >> 16) & 0xFF) as u8;
+    Ok(Ics(stundenplan_to_ics(&stundenplan, client.user())))
+}
 
-    format!("#{:02X}{:02X}{:02X}", r, g, b)
+pub async fn get_reminders<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<Reminders>, ResponseError> {
+    let reminders = client.reminders().await?;
+
+    let latest = reminders
+        .latest
+        .into_iter()
+        .map(|latest| LatestGradeReminder {
+            graded_on: parse_sap_date(&latest.agrdate),
+            booked_on: parse_sap_date(&latest.bookdate),
+            grade: parse_sap_number(&latest.gradesymbol),
+            credit_points: parse_sap_number(&latest.cpgraded),
+            name: latest.awobject,
+            short_name: latest.awobject_short,
+            academic_year: latest.acad_year,
+            academic_session: latest.acad_session,
+            grade_symbol: latest.gradesymbol,
+            credit_point_unit: latest.cpunit,
+            assessment_type: latest.agrtype,
+            object_type: latest.awotype,
+            status: latest.awstatus,
+            booking_reason: latest.bookreason,
+        })
+        .collect();
+
+    let upcoming = reminders
+        .upcoming
+        .into_iter()
+        .map(|upcoming| UpcomingEventReminder {
+            start: parse_sap_datetime(&upcoming.evdat, &upcoming.beguz),
+            end: parse_sap_datetime(&upcoming.evdat, &upcoming.enduz),
+            id: upcoming.objid,
+            title: upcoming.sm_stext,
+            short_title: upcoming.sm_short,
+            instructor: upcoming.instructor,
+            short_instructor: upcoming.sinstructor,
+            room: upcoming.room,
+            short_room: upcoming.sroom,
+            location: upcoming.location,
+            comment: upcoming.comment,
+        })
+        .collect();
+
+    Ok(Json(Reminders {
+        semester: reminders.semester,
+        exams: reminders.exams,
+        electives: reminders.electives,
+        latest,
+        upcoming,
+    }))
 }
 
-pub async fn get_reminders(
-    Extension(cd_authdata): Extension<CdAuthData>,
-) -> Result<Json<CampusReminders>, ResponseError> {
-    let client = get_client_default(true)?;
+// SAP dates are "yyyymmdd", unset ones "00000000"
+fn parse_sap_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y%m%d").ok()
+}
 
-    let user = cd_authdata.user;
-    let hash = cd_authdata.hash;
+// SAP date plus "hhmmss" time, both in CampusDual's local time
+fn parse_sap_datetime(date: &str, time: &str) -> Option<DateTime<FixedOffset>> {
+    let time = NaiveTime::parse_from_str(time, "%H%M%S").ok()?;
 
-    let resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/dash/getreminders?user={user}&hash={hash}"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<CampusReminders>()
-        .await?;
+    parse_sap_date(date)?
+        .and_time(time)
+        .and_local_timezone(CD_TIMEZONE)
+        .earliest()
+        .map(|datetime| datetime.fixed_offset())
+}
 
-    Ok(Json(resp))
+// "2,7" as well as "10.00", anything else (e.g. "B" for passed) is None
+fn parse_sap_number(number: &str) -> Option<f32> {
+    number.trim().replace(',', ".").parse().ok()
 }
 
-pub async fn get_timeline(
-    Extension(cd_authdata): Extension<CdAuthData>,
+pub async fn get_timeline<C: CampusDual>(
+    Extension(client): Extension<C>,
 ) -> Result<Json<ExportTimelineEvents>, ResponseError> {
-    let client = get_client_default(true)?;
-    let resp = client
-        .get(format!(
-            "https://selfservice.campus-dual.de/dash/gettimeline?user={}",
-            cd_authdata.user
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<CampusTimeline>()
-        .await;
-
-    if let Ok(timeline) = resp {
-        let events = timeline.events;
-
-        let fachsemester: Vec<ExportTimelineEvent> = events_by_color("#fcbe04", &events);
-        let theoriesemester: Vec<ExportTimelineEvent> = events_by_color("#0070a3", &events);
-        let praxissemester: Vec<ExportTimelineEvent> = events_by_color("#119911", &events);
-        let specials: Vec<ExportTimelineEvent> = events_by_color("#880000", &events);
-
+    if let Some(events) = client.timeline().await? {
         let export_events = ExportTimelineEvents {
-            fachsemester,
-            theoriesemester,
-            praxissemester,
-            specials,
+            fachsemester: events_by_kind(TimelineEventKind::Fachsemester, &events),
+            theoriesemester: events_by_kind(TimelineEventKind::Theoriesemester, &events),
+            praxissemester: events_by_kind(TimelineEventKind::Praxissemester, &events),
+            specials: events_by_kind(TimelineEventKind::Special, &events),
         };
 
         Ok(Json(export_events))
@@ -399,29 +367,133 @@ pub async fn get_timeline(
     }
 }
 
-fn events_by_color(color: &str, events: &[CampusTimelineEvent]) -> Vec<ExportTimelineEvent> {
+pub async fn get_timeline_events<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Json<Vec<TimelineEvent>>, ResponseError> {
+    let events = client
+        .timeline()
+        .await?
+        .ok_or_else(invalid_timeline_error)?;
+
+    Ok(Json(events))
+}
+
+pub async fn get_timeline_ics<C: CampusDual>(
+    Extension(client): Extension<C>,
+) -> Result<Ics, ResponseError> {
+    let events = client
+        .timeline()
+        .await?
+        .ok_or_else(invalid_timeline_error)?;
+
+    Ok(Ics(timeline_to_ics(&events, client.user())))
+}
+
+// Home screen data in one request, the CampusDual calls run concurrently
+pub async fn get_dashboard<C: CampusDual>(Extension(client): Extension<C>) -> Json<Dashboard> {
+    let (ects, fachsem, examstats, reminders, timeline) = tokio::join!(
+        get_ects(Extension(client.clone())),
+        get_fachsem(Extension(client.clone())),
+        get_examstats(Extension(client.clone())),
+        get_reminders(Extension(client.clone())),
+        get_timeline(Extension(client)),
+    );
+
+    Json(Dashboard {
+        ects: ects.map(|Json(ects)| ects).into(),
+        fachsem: fachsem.map(|Json(fachsem)| fachsem).into(),
+        examstats: examstats.map(|Json(examstats)| examstats).into(),
+        reminders: reminders.map(|Json(reminders)| reminders).into(),
+        timeline: timeline.map(|Json(timeline)| timeline).into(),
+    })
+}
+
+pub fn invalid_timeline_error() -> ResponseError {
+    ResponseError {
+        message: "CampusDual returned an invalid timeline".to_string(),
+        status_code: StatusCode::BAD_GATEWAY,
+    }
+}
+
+// Resolves the requested days (default: current week) into CampusDual's unix second window
+pub fn stundenplan_range(query: &StundenplanQuery) -> Result<(i64, i64), ResponseError> {
+    let invalid_range = |message: &str| ResponseError {
+        message: message.to_string(),
+        status_code: StatusCode::BAD_REQUEST,
+    };
+
+    let (from, to) = match (query.from, query.to) {
+        (Some(from), Some(to)) => (Some(from), Some(to)),
+        (Some(from), None) => (Some(from), from.checked_add_days(Days::new(6))),
+        (None, Some(to)) => (to.checked_sub_days(Days::new(6)), Some(to)),
+        (None, None) => {
+            let today = Utc::now().with_timezone(&CD_TIMEZONE).date_naive();
+            let monday = today.week(Weekday::Mon).first_day();
+            (Some(monday), monday.checked_add_days(Days::new(6)))
+        }
+    };
+    let (Some(from), Some(to)) = (from, to) else {
+        return Err(invalid_range("Date out of range"));
+    };
+
+    if from > to {
+        return Err(invalid_range("'from' must not be after 'to'"));
+    }
+    if (to - from).num_days() + 1 > STUNDENPLAN_MAX_SPAN_DAYS {
+        return Err(invalid_range(&format!(
+            "Date range must not exceed {STUNDENPLAN_MAX_SPAN_DAYS} days"
+        )));
+    }
+
+    let start = local_midnight_timestamp(from);
+    let end = to
+        .checked_add_days(Days::new(1))
+        .and_then(local_midnight_timestamp);
+
+    match (start, end) {
+        (Some(start), Some(end)) => Ok((start, end)),
+        _ => Err(invalid_range("Date out of range")),
+    }
+}
+
+fn local_midnight_timestamp(date: NaiveDate) -> Option<i64> {
+    date.and_time(NaiveTime::MIN)
+        .and_local_timezone(CD_TIMEZONE)
+        .earliest()
+        .map(|datetime| datetime.timestamp())
+}
+
+fn string_to_rgb(input: &str) -> String {
+    // Create a hasher
+    let mut hasher = FnvHasher::default();
+
+    // Hash the input string
+    input.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    // Extract RGB components from the hash
+    let r = (hash & 0xFF) as u8;
+    let g = ((hash >> 8) & 0xFF) as u8;
+    let b = ((hash >> 16) & 0xFF) as u8;
+
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+fn events_by_kind(kind: TimelineEventKind, events: &[TimelineEvent]) -> Vec<ExportTimelineEvent> {
     events
         .iter()
-        .filter(|event| event.color == color)
+        .filter(|event| event.kind == kind)
         .map(|event| ExportTimelineEvent {
-            name: event.title.clone(),
-            description: event
-                .description
-                .replace("<br>", " ")
-                .replace("<strong>", "")
-                .replace("</strong>", ""),
+            name: event.name.clone(),
+            description: event.description.clone(),
             color: event.color.clone(),
-            start: campusdate_to_iso8601(event.start.as_ref()),
-            end: campusdate_to_iso8601(event.end.as_ref()),
+            start: date_to_iso8601(event.start),
+            end: date_to_iso8601(event.end),
         })
         .collect()
 }
 
-fn campusdate_to_iso8601(input: Option<&String>) -> String {
-    let format = "%a, %d %b %Y %H:%M:%S %z";
-
-    input
-        .and_then(|input| DateTime::parse_from_str(input, format).ok())
-        .map(|parsed| parsed.to_rfc3339())
+fn date_to_iso8601(date: Option<DateTime<FixedOffset>>) -> String {
+    date.map(|date| date.to_rfc3339())
         .unwrap_or("o. D.".to_string())
 }
@@ -0,0 +1,611 @@
+use http::{StatusCode, header::COOKIE};
+use serde_json::{Value, json};
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::Aead};
+use base64::prelude::*;
+use jsonwebtoken::{EncodingKey, Header};
+
+use super::mock_campusdual::{
+    MOCK_MAINTENANCE_USER, MOCK_PASSWORD, MOCK_USER, OLD_AES_KEY, OLD_JWT_SECRET, init, spawn_api,
+};
+use crate::{
+    auth::{decode_jwt, encode_jwt},
+    campus_backend::{
+        login::cdlogin_get_jcookie_and_meta,
+        req_client_funcs::{cd_cookie_header, get_client_default},
+    },
+    constants::{REVIVE_SECRET_HEADER, vault},
+    encryption::{decrypt, decrypt_urlsafe, encrypt_urlsafe, seal_password},
+    types::{CampusLoginData, CdAuthData, FeedTokenData, SealedPassword, TokenSession, TokenType},
+};
+
+async fn sign_in(api: &str) -> String {
+    sign_in_with_secret(api).await.0
+}
+
+// The whole login response
+async fn sign_in_body(api: &str) -> Value {
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/signin"))
+        .json(&json!({ "username": MOCK_USER, "password": MOCK_PASSWORD }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    resp.json().await.unwrap()
+}
+
+// (token, revive secret)
+async fn sign_in_with_secret(api: &str) -> (String, String) {
+    let body = sign_in_body(api).await;
+    (
+        body["token"].as_str().unwrap().to_string(),
+        body["revive_secret"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn get(api: &str, token: &str, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{api}{path}"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_json(api: &str, token: &str, path: &str) -> Value {
+    let resp = get(api, token, path).await;
+    assert_eq!(resp.status(), StatusCode::OK, "GET {path}");
+    resp.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn signin_returns_token_and_user_info() {
+    let api = spawn_api().await;
+
+    let body = sign_in_body(&api).await;
+
+    assert!(
+        body["token"]
+            .as_str()
+            .is_some_and(|token| !token.is_empty())
+    );
+    assert_eq!(body["user"]["user"], MOCK_USER);
+    assert_eq!(body["user"]["first_name"], "Erika");
+    assert_eq!(body["user"]["last_name"], "Mustermann");
+    assert_eq!(body["user"]["seminar_group"], "CS22-1");
+    assert_eq!(body["user"]["seminar_name"], "Informatik");
+
+    // identity for services that only verify the token
+    let claims = decode_jwt(body["token"].as_str().unwrap(), TokenType::Access)
+        .unwrap()
+        .claims;
+    assert_eq!(claims.sub, MOCK_USER);
+    assert_eq!(claims.seminar_group, "CS22-1");
+
+    // shared secrets are never published
+    let jwks: Value = reqwest::get(format!("{api}/.well-known/jwks.json"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(jwks, json!({ "keys": [] }));
+}
+
+#[tokio::test]
+async fn signin_with_wrong_password_fails() {
+    let api = spawn_api().await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/signin"))
+        .json(&json!({ "username": MOCK_USER, "password": "wrong" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn refresh_token_only_issues_access_tokens() {
+    let api = spawn_api().await;
+
+    let body = sign_in_body(&api).await;
+    let token = body["token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+
+    let refresh = |bearer: String| {
+        reqwest::Client::new()
+            .post(format!("{api}/token/refresh"))
+            .bearer_auth(bearer)
+            .send()
+    };
+
+    let resp = get(&api, refresh_token, "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = refresh(token.to_string()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = refresh(refresh_token.to_string()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let new_token: Value = resp.json().await.unwrap();
+    let resp = get(&api, new_token["token"].as_str().unwrap(), "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn signout_revokes_access_and_refresh_token() {
+    let api = spawn_api().await;
+
+    let body = sign_in_body(&api).await;
+    let token = body["token"].as_str().unwrap();
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/signout"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = get(&api, token, "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/token/refresh"))
+        .bearer_auth(refresh_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotated_keys_still_verify() {
+    let api = spawn_api().await;
+    let token = sign_in(&api).await;
+
+    // re-issue the same claims the way the API did before the "test" keys were rolled out
+    let mut claims = decode_jwt(&token, TokenType::Access).unwrap().claims;
+    let cd_auth_data = decrypt(&claims.nonce, &claims.cipher).unwrap();
+    let ciphertext = Aes256Gcm::new(OLD_AES_KEY.into())
+        .encrypt(Nonce::from_slice(&[7; 12]), cd_auth_data.as_bytes())
+        .unwrap();
+    claims.nonce = BASE64_STANDARD.encode([7; 12]);
+    claims.cipher = format!("old:{}", BASE64_STANDARD.encode(ciphertext));
+
+    let sign = |kid: Option<&str>| {
+        let header = Header {
+            kid: kid.map(str::to_string),
+            ..Header::default()
+        };
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(OLD_JWT_SECRET)).unwrap()
+    };
+
+    let resp = get(&api, &sign(Some("old")), "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // no kid means the default key ID, which the test key ring doesn't have
+    for kid in [None, Some("test"), Some("unknown")] {
+        let resp = get(&api, &sign(kid), "/get_grades").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "kid {kid:?}");
+    }
+
+    // validly signed, but the session data was encrypted with an AES key that's gone
+    claims.cipher = claims.cipher.replacen("old:", "unknown:", 1);
+    let header = Header {
+        kid: Some("old".to_string()),
+        ..Header::default()
+    };
+    let token =
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(OLD_JWT_SECRET)).unwrap();
+    let resp = get(&api, &token, "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn requests_without_jwt_are_rejected() {
+    let api = spawn_api().await;
+
+    let resp = reqwest::Client::new()
+        .get(format!("{api}/get_grades"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = get(&api, "not-a-jwt", "/get_grades").await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn check_revive_session(api: &str, token: &str, secret: Option<&str>) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .get(format!("{api}/check_revive_session"))
+        .bearer_auth(token);
+    if let Some(secret) = secret {
+        request = request.header(REVIVE_SECRET_HEADER, secret);
+    }
+
+    request.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn live_session_is_not_revived() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+
+    let resp = check_revive_session(&api, &token, Some(&secret)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.json::<Value>().await.unwrap(), Value::Null);
+
+    let resp = check_revive_session(&api, &token, None).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+// Same session, but CampusDual doesn't know its cookie anymore
+fn dead_session_token(token: &str) -> String {
+    let claims = decode_jwt(token, TokenType::Access).unwrap().claims;
+    let cd_auth_data = decrypt(&claims.nonce, &claims.cipher).unwrap();
+
+    let mut cd_auth_data: CdAuthData = serde_json::from_str(&cd_auth_data).unwrap();
+    cd_auth_data.cookie = cd_auth_data.cookie.replace("mock-session", "expired");
+    encode_jwt(
+        cd_auth_data,
+        SealedPassword(claims.sealed_password),
+        TokenType::Access,
+        TokenSession {
+            jti: claims.jti,
+            seminar_group: claims.seminar_group,
+        },
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn dead_session_is_revived_with_the_secret() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+
+    // the token must not carry anything that opens without the secret
+    let claims = decode_jwt(&token, TokenType::Access).unwrap().claims;
+    let cd_auth_data = decrypt(&claims.nonce, &claims.cipher).unwrap();
+    assert!(!cd_auth_data.contains(MOCK_PASSWORD));
+    assert!(!claims.sealed_password.contains(MOCK_PASSWORD));
+
+    let dead_token = dead_session_token(&token);
+
+    let resp = check_revive_session(&api, &dead_token, Some("bm90LXRoZS1zZWNyZXQ")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = check_revive_session(&api, &dead_token, Some(&secret)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["user"]["user"], MOCK_USER);
+    assert_ne!(body["revive_secret"], secret.as_str());
+
+    let grades = get(&api, body["token"].as_str().unwrap(), "/get_grades").await;
+    assert_eq!(grades.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn signout_after_revive_revokes_the_tokens_from_before() {
+    let api = spawn_api().await;
+    let body = sign_in_body(&api).await;
+    let refresh_token = body["refresh_token"].as_str().unwrap();
+    let secret = body["revive_secret"].as_str().unwrap();
+
+    let dead_token = dead_session_token(body["token"].as_str().unwrap());
+    let resp = check_revive_session(&api, &dead_token, Some(secret)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let revived: Value = resp.json().await.unwrap();
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/signout"))
+        .bearer_auth(revived["token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/token/refresh"))
+        .bearer_auth(refresh_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn grades_and_grade_stats() {
+    let api = spawn_api().await;
+    let token = sign_in(&api).await;
+
+    let grades = get_json(&api, &token, "/get_grades").await;
+    let grades = grades.as_array().unwrap();
+    assert_eq!(grades.len(), 4);
+    // newest bekanntgabe first
+    assert_eq!(grades[0]["name"], "Programmierung");
+    assert_eq!(grades[0]["credit_points"], 10);
+    assert_eq!(grades[0]["subgrades"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        grades[0]["subgrades"][0]["bekanntgabe"]
+            .as_str()
+            .map(str::len),
+        Some(10)
+    );
+    assert!(grades[0]["grade"]["kind"].is_string());
+
+    let changes = get_json(&api, &token, "/get_grade_changes").await;
+    assert_eq!(changes["grades"].as_array().unwrap().len(), 4);
+    let fingerprint = changes["fingerprint"].as_str().unwrap();
+    let changes = get_json(
+        &api,
+        &token,
+        &format!("/get_grade_changes?fingerprint={fingerprint}"),
+    )
+    .await;
+    assert_eq!(changes["grades"], json!([]));
+
+    let mut request = grades[0]["subgrades"][0]["internal_metadata"].clone();
+    request["grade"] = json!(2.7);
+    let stats: Value = reqwest::Client::new()
+        .post(format!("{api}/get_gradestats"))
+        .bearer_auth(&token)
+        .json(&request)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        stats,
+        json!({
+            "one": 4, "two": 9, "three": 7, "four": 3, "ronmodus": 2, "other": {},
+            "mean": 2.6, "median": 2.0, "pass_rate": 92.0, "percentile_rank": 34.0
+        })
+    );
+}
+
+#[tokio::test]
+async fn exam_signup_and_verfahren() {
+    let api = spawn_api().await;
+    let token = sign_in(&api).await;
+
+    let signup = get_json(&api, &token, "/get_examsignup").await;
+    assert_eq!(signup[0]["name"], "Datenbanken");
+    assert_eq!(signup[0]["exam_date"], "17.02.2025");
+    assert_eq!(signup[0]["signup_until"], "03.02.2025");
+    assert_eq!(signup[0]["internal_metadata"]["assessment"], "50001234");
+
+    let verfahren = get_json(&api, &token, "/get_examverfahren").await;
+    assert_eq!(verfahren["options"][0]["name"], "Betriebssysteme");
+    assert_eq!(verfahren["options"][0]["signoff_until"], "05.02.2025");
+    assert_eq!(verfahren["warnings"], json!([]));
+}
+
+#[tokio::test]
+async fn dashboard_endpoints() {
+    let api = spawn_api().await;
+    let token = sign_in(&api).await;
+
+    let ects = get_json(&api, &token, "/get_ects").await;
+    assert_eq!(ects, json!({ "earned": 120, "required": 180 }));
+
+    let fachsem = get_json(&api, &token, "/get_fachsem").await;
+    assert_eq!(fachsem, json!({ "semester": 4 }));
+
+    let examstats = get_json(&api, &token, "/get_examstats").await;
+    assert_eq!(examstats["total"], 14);
+    assert_eq!(examstats["successful"], 11);
+
+    let reminders = get_json(&api, &token, "/get_reminders").await;
+    assert_eq!(reminders["semester"], 4);
+    assert_eq!(reminders["latest"][0]["short_name"], "5CS-PRG-KL");
+    assert_eq!(reminders["latest"][0]["graded_on"], "2024-08-02");
+    assert_eq!(reminders["latest"][0]["grade"], 2.7);
+    assert_eq!(reminders["latest"][0]["credit_points"], 10.0);
+    assert_eq!(reminders["upcoming"][0]["title"], "Datenbanken Vorlesung");
+    assert_eq!(
+        reminders["upcoming"][0]["start"],
+        "2025-01-20T08:30:00+01:00"
+    );
+    assert_eq!(reminders["upcoming"][0]["end"], "2025-01-20T10:00:00+01:00");
+
+    let timeline = get_json(&api, &token, "/get_timeline").await;
+    assert_eq!(timeline["theoriesemester"][0]["name"], "Theoriephase");
+    assert_eq!(timeline["specials"][0]["start"], "o. D.");
+
+    let dashboard = get_json(&api, &token, "/dashboard").await;
+    assert_eq!(dashboard["fachsem"]["data"]["semester"], 4);
+    assert_eq!(dashboard["examstats"]["data"]["total"], 14);
+    assert_eq!(dashboard["reminders"]["data"]["semester"], 4);
+    assert_eq!(
+        dashboard["timeline"]["data"]["theoriesemester"][0]["name"],
+        "Theoriephase"
+    );
+    for section in ["ects", "fachsem", "examstats", "reminders", "timeline"] {
+        assert_eq!(dashboard[section]["error"], Value::Null, "{section}");
+    }
+}
+
+#[tokio::test]
+async fn stundenplan_range_and_ics() {
+    let api = spawn_api().await;
+    let token = sign_in(&api).await;
+
+    let items = get_json(
+        &api,
+        &token,
+        "/get_stundenplan?from=2025-01-20&to=2025-01-26",
+    )
+    .await;
+    assert_eq!(items[0]["start"], 1737361800000i64);
+    assert_eq!(items[0]["color"], "#D41610");
+
+    let resp = get(
+        &api,
+        &token,
+        "/get_stundenplan?from=2025-01-26&to=2025-01-20",
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = get(
+        &api,
+        &token,
+        "/get_stundenplan?from=2025-01-01&to=2026-01-01",
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = get(&api, &token, "/get_stundenplan.ics?from=2025-01-20").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let ics = resp.text().await.unwrap();
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+    assert!(ics.contains("DTSTART;TZID=Europe/Berlin:20250120T093000\r\n"));
+    assert!(ics.contains("LOCATION:Hörsaal 1\r\n"));
+}
+
+async fn create_feed(api: &str, token: &str, secret: &str) -> Value {
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/feed/create"))
+        .bearer_auth(token)
+        .header(REVIVE_SECRET_HEADER, secret)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    resp.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn calendar_feeds() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+
+    let links = create_feed(&api, &token, &secret).await;
+
+    for path in ["stundenplan", "exams", "timeline"] {
+        let resp = reqwest::get(format!("{api}{}", links[path].as_str().unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "{path} feed");
+        assert!(resp.text().await.unwrap().contains("BEGIN:VEVENT"));
+    }
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/feed/revoke"))
+        .bearer_auth(&token)
+        .json(&json!({ "token": links["token"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = reqwest::get(format!("{api}{}", links["stundenplan"].as_str().unwrap()))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn exam_feed_with_changed_password_is_gone() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+
+    let links = create_feed(&api, &token, &secret).await;
+
+    // same feed, but the password it holds isn't accepted anymore
+    let mut feed: FeedTokenData =
+        serde_json::from_str(&decrypt_urlsafe(links["token"].as_str().unwrap()).unwrap()).unwrap();
+    let (sealed, revive_secret) = seal_password("changed").unwrap();
+    feed.sealed_password = SealedPassword(sealed);
+    feed.vault_id = vault().insert(&revive_secret, Some(feed.exp)).unwrap();
+    let feed_token = encrypt_urlsafe(&serde_json::to_string(&feed).unwrap()).unwrap();
+
+    // the second poll must not try to log in again
+    for _ in 0..2 {
+        let resp = reqwest::get(format!("{api}/feed/{feed_token}/exams.ics"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::GONE);
+    }
+}
+
+#[tokio::test]
+async fn signout_revokes_feeds_of_the_session() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+    let links = create_feed(&api, &token, &secret).await;
+    let stundenplan = format!("{api}{}", links["stundenplan"].as_str().unwrap());
+
+    let resp = reqwest::get(&stundenplan).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = reqwest::Client::new()
+        .post(format!("{api}/signout"))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = reqwest::get(&stundenplan).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn exam_feed_survives_a_campusdual_outage() {
+    let api = spawn_api().await;
+    let (token, secret) = sign_in_with_secret(&api).await;
+    let links = create_feed(&api, &token, &secret).await;
+
+    // a login that fails for any other reason than the password mustn't end the feed
+    let mut feed: FeedTokenData =
+        serde_json::from_str(&decrypt_urlsafe(links["token"].as_str().unwrap()).unwrap()).unwrap();
+    feed.cd_auth_data.user = MOCK_MAINTENANCE_USER.to_string();
+    let feed_token = encrypt_urlsafe(&serde_json::to_string(&feed).unwrap()).unwrap();
+
+    let resp = reqwest::get(format!("{api}/feed/{feed_token}/exams.ics"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+
+    // the failed attempt still counts against the feed's re-login limit
+    let resp = reqwest::get(format!("{api}/feed/{feed_token}/exams.ics"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn session_cookie_only_survives_same_host_redirects() {
+    let origin = init();
+    let (cd_auth_data, _) = cdlogin_get_jcookie_and_meta(CampusLoginData {
+        username: MOCK_USER.to_string(),
+        password: MOCK_PASSWORD.to_string(),
+    })
+    .await
+    .unwrap();
+    let cookie = cd_cookie_header(&cd_auth_data.cookie).unwrap();
+
+    let follow = |target: &str| {
+        get_client_default(false)
+            .get(format!("{origin}/redirect/{target}"))
+            .header(COOKIE, cookie.clone())
+            .send()
+    };
+
+    assert_eq!(follow("same").await.unwrap().status(), StatusCode::OK);
+    // the hand-set header is dropped on a cross-host hop, the session is lost there
+    assert_eq!(
+        follow("cross").await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
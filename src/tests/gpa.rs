@@ -0,0 +1,49 @@
+use serde_json::json;
+
+use super::fixture_grades;
+use crate::{gpa::gpa_summary, types::WhatIfGrade};
+
+#[test]
+fn gpa_skips_pass_fail_and_zero_credit_rows() {
+    let summary = gpa_summary(&fixture_grades(), &[]).unwrap();
+    let summary = serde_json::to_value(summary).unwrap();
+
+    // Analysis 1,0 and Theoretische Informatik 4,0 with 5 CP each,
+    // "mit Erfolg" only adds earned credit points, the Teilprüfung nothing
+    assert_eq!(
+        summary["overall"],
+        json!({ "gpa": 2.5, "graded_credit_points": 10, "earned_credit_points": 13, "graded_modules": 2 })
+    );
+    assert_eq!(
+        summary["semesters"],
+        json!([
+            { "akad_period": "WS 2022/23", "gpa": 1.0, "graded_credit_points": 5, "earned_credit_points": 5, "graded_modules": 1 },
+            { "akad_period": "SS 2023", "gpa": 4.0, "graded_credit_points": 5, "earned_credit_points": 8, "graded_modules": 1 }
+        ])
+    );
+    assert_eq!(summary["hypothetical"], false);
+}
+
+#[test]
+fn gpa_what_if_fills_in_pending_modules() {
+    let what_if = [WhatIfGrade {
+        module: "Softwaretechnik".to_string(),
+        grade: 2.0,
+    }];
+    let summary = serde_json::to_value(gpa_summary(&fixture_grades(), &what_if).unwrap()).unwrap();
+
+    assert_eq!(summary["overall"]["gpa"], 2.25);
+    assert_eq!(summary["overall"]["earned_credit_points"], 23);
+    assert_eq!(summary["semesters"][2]["akad_period"], "WS 2024/25");
+    assert_eq!(summary["hypothetical"], true);
+
+    // graded modules and unknown names are rejected instead of silently ignored
+    for module in ["Analysis", "Kochen"] {
+        let what_if = [WhatIfGrade {
+            module: module.to_string(),
+            grade: 1.0,
+        }];
+        let err = gpa_summary(&fixture_grades(), &what_if).unwrap_err();
+        assert_eq!(err.status_code, http::StatusCode::BAD_REQUEST);
+    }
+}
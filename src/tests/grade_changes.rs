@@ -0,0 +1,54 @@
+use chrono::NaiveDate;
+
+use super::fixture_grades;
+use crate::{
+    grade_changes::{changed_grades, grade_fingerprint, parse_fingerprint},
+    types::{CampusDualGrade, Grade},
+};
+
+fn names(grades: &[CampusDualGrade]) -> Vec<(&str, usize)> {
+    grades
+        .iter()
+        .map(|grade| (grade.name.as_str(), grade.subgrades.len()))
+        .collect()
+}
+
+#[test]
+fn unchanged_grades_match_their_fingerprint() {
+    let fingerprint = grade_fingerprint(&fixture_grades());
+    let known = parse_fingerprint(&fingerprint).unwrap();
+
+    assert_eq!(grade_fingerprint(&fixture_grades()), fingerprint);
+    assert!(changed_grades(fixture_grades(), None, Some(&known)).is_empty());
+    assert_eq!(changed_grades(fixture_grades(), None, None).len(), 6);
+
+    assert!(parse_fingerprint("not a fingerprint!").is_err());
+}
+
+#[test]
+fn changes_since_a_date_or_fingerprint() {
+    let since = NaiveDate::from_ymd_opt(2023, 10, 1);
+    let changed = changed_grades(fixture_grades(), since, None);
+    // only the retake of Theoretische Informatik, not the failed first attempt
+    assert_eq!(
+        names(&changed),
+        [
+            ("Teilprüfung Englisch B2", 1),
+            ("Theoretische Informatik", 1)
+        ]
+    );
+
+    let known = parse_fingerprint(&grade_fingerprint(&fixture_grades())).unwrap();
+    let mut grades = fixture_grades();
+    let softwaretechnik = grades
+        .iter_mut()
+        .find(|grade| grade.name == "Softwaretechnik")
+        .unwrap();
+    softwaretechnik.grade = Grade::Numeric(1.7);
+    softwaretechnik.subgrades[0].grade = Grade::Numeric(1.7);
+
+    assert_eq!(
+        names(&changed_grades(grades, None, Some(&known))),
+        [("Softwaretechnik", 1)]
+    );
+}
@@ -0,0 +1,186 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{Json, Router, extract::State, routing::post};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+use super::mock_campusdual::{MOCK_PASSWORD, MOCK_USER, init};
+use crate::{
+    constants::vault,
+    encryption::seal_password,
+    grade_watcher::{
+        ALLOWED_HOSTS, CheckOutcome, NotifyTarget, WatchedUser, check_target, check_user, is_public,
+    },
+    types::{CdAuthData, SealedPassword},
+};
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+async fn spawn_webhook() -> (String, Received) {
+    let received = Received::default();
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(received): State<Received>, Json(body): Json<Value>| async move {
+                    received.lock().unwrap().push(body);
+                },
+            ),
+        )
+        .with_state(received.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    // the mock webhook is local, which targets normally mustn't be
+    ALLOWED_HOSTS.get_or_init(|| vec!["127.0.0.1".to_string()]);
+
+    (format!("http://{addr}/hook"), received)
+}
+
+fn watched_user(url: String, fingerprint: Option<String>) -> WatchedUser {
+    watched_user_with_password(url, fingerprint, MOCK_PASSWORD)
+}
+
+fn watched_user_with_password(
+    url: String,
+    fingerprint: Option<String>,
+    password: &str,
+) -> WatchedUser {
+    let (sealed_password, revive_secret) = seal_password(password).unwrap();
+
+    WatchedUser {
+        cd_auth_data: CdAuthData {
+            cookie: String::new(),
+            hash: String::new(),
+            user: MOCK_USER.to_string(),
+        },
+        sealed_password: SealedPassword(sealed_password),
+        vault_id: vault().insert(&revive_secret, None).unwrap(),
+        target: NotifyTarget::Webhook { url },
+        fingerprint,
+    }
+}
+
+async fn checked_fingerprint(user: WatchedUser) -> String {
+    match check_user(&user).await.unwrap() {
+        CheckOutcome::Checked(fingerprint) => fingerprint,
+        CheckOutcome::LoginRejected => panic!("login rejected"),
+    }
+}
+
+#[tokio::test]
+async fn watcher_notifies_only_about_changes() {
+    init();
+    let (url, received) = spawn_webhook().await;
+
+    // first check only records the fingerprint
+    let fingerprint = checked_fingerprint(watched_user(url.clone(), None)).await;
+    checked_fingerprint(watched_user(url.clone(), Some(fingerprint.clone()))).await;
+    assert!(received.lock().unwrap().is_empty());
+
+    // an empty fingerprint knows no grades, so all of them are new
+    let new_fingerprint = checked_fingerprint(watched_user(url, Some(String::new()))).await;
+    assert_eq!(new_fingerprint, fingerprint);
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["event"], "grades");
+    assert_eq!(received[0]["user"], MOCK_USER);
+    assert_eq!(received[0]["grades"].as_array().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn watcher_reuses_live_sessions() {
+    init();
+    let (url, _) = spawn_webhook().await;
+    let user = watched_user(url, None);
+
+    checked_fingerprint(user.clone()).await;
+
+    // without the revive secret, only the cached session can pass the second check
+    vault().remove(&user.vault_id).unwrap();
+    checked_fingerprint(user).await;
+}
+
+#[tokio::test]
+async fn rejected_login_is_reported() {
+    init();
+    let (url, received) = spawn_webhook().await;
+
+    let user = watched_user_with_password(url, None, "changed");
+    assert!(matches!(
+        check_user(&user).await.unwrap(),
+        CheckOutcome::LoginRejected
+    ));
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn notification_targets_must_be_public() {
+    ALLOWED_HOSTS.get_or_init(|| vec!["127.0.0.1".to_string()]);
+
+    for url in [
+        "http://localhost:8080/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1/hook",
+        "http://[::1]/hook",
+        "http://[::ffff:192.168.0.1]/hook",
+        "file:///etc/passwd",
+    ] {
+        let target = NotifyTarget::Webhook {
+            url: url.to_string(),
+        };
+        assert!(check_target(&target).await.is_err(), "{url}");
+    }
+
+    let target = NotifyTarget::Ntfy {
+        url: "http://127.0.0.1:8080/topic".to_string(),
+    };
+    assert!(check_target(&target).await.is_ok());
+}
+
+#[test]
+fn internal_address_ranges_are_not_public() {
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.0.1",
+        "169.254.169.254",
+        "0.1.2.3",
+        "100.64.0.1",
+        "100.100.100.200",
+        "100.127.255.254",
+        "198.18.0.1",
+        "198.19.255.254",
+        "240.0.0.1",
+        "255.255.255.255",
+        "::1",
+        "::",
+        "fc00::1",
+        "fe80::1",
+        "::ffff:10.0.0.1",
+        "::10.0.0.1",
+        "::127.0.0.1",
+        "64:ff9b::a9fe:a9fe",
+        "64:ff9b:1::1",
+        "2002:c0a8:1::1",
+        "2002:a9fe:a9fe::1",
+    ] {
+        assert!(!is_public(ip.parse().unwrap()), "{ip}");
+    }
+
+    for ip in [
+        "1.1.1.1",
+        "100.63.255.255",
+        "100.128.0.1",
+        "198.20.0.1",
+        "2606:4700::1111",
+        "64:ff9b::101:101",
+        "2002:101:101::1",
+    ] {
+        assert!(is_public(ip.parse().unwrap()), "{ip}");
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Result, bail};
+use axum::{
+    Extension, Router,
+    routing::{get, post},
+};
+use http::StatusCode;
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+use crate::{
+    campus_backend::client::CampusDual,
+    services,
+    types::{
+        CampusDualGrade, CampusDualSignupOption, CampusDualVerfahrenOptions, CampusReminders,
+        CdExamDetails, CdExamStats, CdGradeStatEntry, ExamRegistrationMetadata, StundenplanItem,
+        SubGradeMetadata, TimelineEvent,
+    },
+};
+
+// Canned answers instead of CampusDual, calls it doesn't expect fail
+#[derive(Clone)]
+struct FakeCampusDual {
+    fachsem: &'static str,
+}
+
+impl CampusDual for FakeCampusDual {
+    fn user(&self) -> &str {
+        "3000000"
+    }
+
+    async fn session_alive(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    async fn grades(&self) -> Result<Vec<CampusDualGrade>> {
+        bail!("not faked")
+    }
+
+    async fn grade_distribution(&self, meta: &SubGradeMetadata) -> Result<Vec<CdGradeStatEntry>> {
+        assert_eq!(meta.module, "5CS-DB");
+
+        Ok([("sehr gut", 3), ("gut", 5), ("mit Erfolg", 2)]
+            .map(|(gradetext, count)| CdGradeStatEntry {
+                gradetext: gradetext.to_string(),
+                count,
+            })
+            .into())
+    }
+
+    async fn exam_signups(&self) -> Result<Vec<CampusDualSignupOption>> {
+        bail!("not faked")
+    }
+
+    async fn exam_verfahren(&self) -> Result<CampusDualVerfahrenOptions> {
+        bail!("not faked")
+    }
+
+    async fn exam_details(&self, _: &ExamRegistrationMetadata) -> Result<CdExamDetails> {
+        bail!("not faked")
+    }
+
+    async fn register_exam(&self, _: &ExamRegistrationMetadata) -> Result<String> {
+        bail!("not faked")
+    }
+
+    async fn cancel_exam(&self, _: &ExamRegistrationMetadata) -> Result<String> {
+        bail!("not faked")
+    }
+
+    async fn ects(&self) -> Result<String> {
+        bail!("not faked")
+    }
+
+    async fn fachsem(&self) -> Result<String> {
+        Ok(self.fachsem.to_string())
+    }
+
+    async fn exam_stats(&self) -> Result<CdExamStats> {
+        bail!("not faked")
+    }
+
+    async fn timetable(&self, start: i64, end: i64) -> Result<Vec<StundenplanItem>> {
+        assert!(start < end);
+
+        Ok(serde_json::from_value(json!([{
+            "allDay": false, "color": "darkred", "description": "Datenbanken",
+            "editable": false, "start": start, "end": start + 5400, "instructor": "",
+            "remarks": "", "room": "", "sinstructor": "", "sroom": "", "title": "5CS-DB-VL"
+        }]))?)
+    }
+
+    async fn reminders(&self) -> Result<CampusReminders> {
+        bail!("not faked")
+    }
+
+    async fn timeline(&self) -> Result<Option<Vec<TimelineEvent>>> {
+        Ok(None)
+    }
+}
+
+async fn serve(fake: FakeCampusDual) -> String {
+    let app = Router::new()
+        .route(
+            "/get_gradestats",
+            post(services::get_gradestats::<FakeCampusDual>),
+        )
+        .route("/get_fachsem", get(services::get_fachsem::<FakeCampusDual>))
+        .route(
+            "/get_stundenplan",
+            get(services::get_stundenplan::<FakeCampusDual>),
+        )
+        .route(
+            "/get_timeline",
+            get(services::get_timeline::<FakeCampusDual>),
+        )
+        .route(
+            "/get_timeline_events",
+            get(services::get_timeline_events::<FakeCampusDual>),
+        )
+        .route("/get_grades", get(services::get_grades::<FakeCampusDual>))
+        .route("/dashboard", get(services::get_dashboard::<FakeCampusDual>))
+        .layer(Extension(fake));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn gradestats_keep_unknown_grade_texts() {
+    let api = serve(FakeCampusDual { fachsem: "4" }).await;
+
+    let stats: Value = reqwest::Client::new()
+        .post(format!("{api}/get_gradestats"))
+        .json(&json!({ "module": "5CS-DB", "peryr": "2024", "perid": "001", "grade": 1.3 }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        stats,
+        json!({
+            "one": 3, "two": 5, "three": 0, "four": 0, "ronmodus": 0,
+            "other": { "mit Erfolg": 2 },
+            "mean": 1.63, "median": 2.0, "pass_rate": 100.0, "percentile_rank": 81.25
+        })
+    );
+}
+
+#[tokio::test]
+async fn stundenplan_is_shaped_for_the_frontend() {
+    let api = serve(FakeCampusDual { fachsem: "4" }).await;
+
+    let items: Value = reqwest::get(format!("{api}/get_stundenplan?from=2025-01-20"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // 2025-01-20 00:00 in Berlin, in milliseconds
+    assert_eq!(items[0]["start"], 1737327600000i64);
+    assert_eq!(items[0]["color"], "#D41610");
+    assert_eq!(items[0]["font_color"], "#FFFFFF");
+}
+
+#[tokio::test]
+async fn fachsem_is_parsed_or_rejected() {
+    let api = serve(FakeCampusDual { fachsem: "\"5\"" }).await;
+
+    let fachsem: Value = reqwest::get(format!("{api}/get_fachsem"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(fachsem, json!({ "semester": 5 }));
+
+    // no more silent fallback to the first semester
+    let api = serve(FakeCampusDual { fachsem: "\"\"" }).await;
+
+    let resp = reqwest::get(format!("{api}/get_fachsem")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn client_errors_become_response_errors() {
+    let api = serve(FakeCampusDual { fachsem: "4" }).await;
+
+    let resp = reqwest::get(format!("{api}/get_grades")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // an unparseable timeline is empty for the legacy endpoint, an error for the typed one
+    let timeline: Value = reqwest::get(format!("{api}/get_timeline"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(timeline["theoriesemester"], json!([]));
+
+    let resp = reqwest::get(format!("{api}/get_timeline_events"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn dashboard_reports_failures_per_section() {
+    let api = serve(FakeCampusDual { fachsem: "4" }).await;
+
+    let resp = reqwest::get(format!("{api}/dashboard")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let dashboard: Value = resp.json().await.unwrap();
+    assert_eq!(
+        dashboard["fachsem"],
+        json!({ "data": { "semester": 4 }, "error": null })
+    );
+    assert_eq!(
+        dashboard["ects"],
+        json!({ "data": null, "error": "Internal Server Error" })
+    );
+    assert_eq!(dashboard["reminders"]["data"], Value::Null);
+    assert_eq!(dashboard["timeline"]["data"]["specials"], json!([]));
+}
@@ -0,0 +1,75 @@
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Header, Validation, decode, encode, jwk::AlgorithmParameters,
+};
+use serde_json::{Value, json};
+
+use crate::encryption::{JwtKey, KeyRing};
+
+fn key_pair(algorithm: Algorithm, name: &str) -> JwtKey {
+    let read = |part: &str| {
+        std::fs::read(format!(
+            "{}/src/tests/fixtures/keys/{name}_{part}.pem",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap()
+    };
+
+    JwtKey::from_pem(algorithm, Some(&read("private")), &read("public")).unwrap()
+}
+
+// What another service does: verify with nothing but the published JWK
+fn verify_with_jwk(key: &JwtKey) {
+    let token = encode(
+        &Header::new(key.algorithm),
+        &json!({ "sub": "3001234", "exp": 4_000_000_000u64 }),
+        key.encoding.as_ref().unwrap(),
+    )
+    .unwrap();
+
+    let jwk = key.jwk.as_ref().unwrap();
+    let decoding_key = DecodingKey::from_jwk(jwk).unwrap();
+    let claims = decode::<Value>(&token, &decoding_key, &Validation::new(key.algorithm))
+        .unwrap()
+        .claims;
+    assert_eq!(claims["sub"], "3001234");
+}
+
+#[test]
+fn ed25519_key_pair_is_published_as_okp() {
+    let key = key_pair(Algorithm::EdDSA, "ed25519");
+
+    match &key.jwk.as_ref().unwrap().algorithm {
+        AlgorithmParameters::OctetKeyPair(okp) => assert_eq!(okp.x.len(), 43),
+        other => panic!("unexpected JWK {other:?}"),
+    }
+    verify_with_jwk(&key);
+}
+
+#[test]
+fn rsa_key_pair_is_published_with_modulus_and_exponent() {
+    let key = key_pair(Algorithm::RS256, "rsa");
+
+    match &key.jwk.as_ref().unwrap().algorithm {
+        AlgorithmParameters::RSA(rsa) => assert_eq!(rsa.e, "AQAB"),
+        other => panic!("unexpected JWK {other:?}"),
+    }
+    verify_with_jwk(&key);
+}
+
+#[test]
+fn shared_secrets_have_no_jwk() {
+    assert!(JwtKey::from_secret(b"secret").jwk.is_none());
+}
+
+#[test]
+fn key_ring_rejects_duplicate_key_ids() {
+    let key_ring = KeyRing::new("2", JwtKey::from_secret(b"new"))
+        .with_previous("1", JwtKey::from_secret(b"old"))
+        .unwrap();
+
+    assert!(
+        key_ring
+            .with_previous("2", JwtKey::from_secret(b"older"))
+            .is_err()
+    );
+}
@@ -0,0 +1,268 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use axum::{
+    Form, Router,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    constants::{
+        AES_KEYS, CD_COOKIE_DOMAIN, CD_ERP_URL, CD_SELFSERVICE_URL, JWT_KEYS,
+        LOGIN_RATELIMIT_QUOTA, LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC, RATELIMIT_QUOTA,
+        RATELIMIT_RESTORE_INTERVAL_SEC,
+    },
+    encryption::{JwtKey, KeyRing},
+    routes,
+};
+
+pub const MOCK_USER: &str = "3001234";
+// logging in as this user hits a maintenance page instead of the login form
+pub const MOCK_MAINTENANCE_USER: &str = "3009999";
+pub const MOCK_PASSWORD: &str = "correct horse battery staple";
+pub const MOCK_HASH: &str = "0123456789abcdef0123456789abcdef";
+pub const OLD_AES_KEY: &[u8; 32] = b"old-aes-key-also-exactly-32-byte";
+pub const OLD_JWT_SECRET: &[u8] = b"old-jwt-secret";
+const MOCK_XSRF: &str = "MOCK_XSRF_TOKEN";
+const MOCK_SESSION: &str = "MYSAPSSO2=mock-session";
+
+static MOCK_ORIGIN: OnceLock<String> = OnceLock::new();
+
+// Starts the mock once per test binary and points the statics at it
+pub fn init() -> &'static str {
+    MOCK_ORIGIN.get_or_init(|| {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let origin = format!("http://localhost:{}", listener.local_addr().unwrap().port());
+
+        // own runtime, so the mock outlives the #[tokio::test] that started it
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    let listener = TcpListener::from_std(listener).unwrap();
+                    axum::serve(listener, mock_app()).await.unwrap();
+                });
+        });
+
+        // "old" stands for a key that was rotated out, see e2e::rotated_keys_still_verify
+        AES_KEYS.get_or_init(|| {
+            KeyRing::new("test", *b"test-aes-key-of-exactly-32-bytes")
+                .with_previous("old", *OLD_AES_KEY)
+                .unwrap()
+        });
+        JWT_KEYS.get_or_init(|| {
+            KeyRing::new("test", JwtKey::from_secret(b"test-jwt-secret"))
+                .with_previous("old", JwtKey::from_secret(OLD_JWT_SECRET))
+                .unwrap()
+        });
+        RATELIMIT_QUOTA.get_or_init(|| 1000);
+        RATELIMIT_RESTORE_INTERVAL_SEC.get_or_init(|| 1);
+        LOGIN_RATELIMIT_QUOTA.get_or_init(|| 1000);
+        LOGIN_RATELIMIT_RESTORE_INTERVAL_SEC.get_or_init(|| 1);
+        CD_SELFSERVICE_URL.get_or_init(|| origin.clone());
+        CD_ERP_URL.get_or_init(|| origin.clone());
+        CD_COOKIE_DOMAIN.get_or_init(|| "localhost".to_string());
+
+        origin
+    })
+}
+
+// Serves routes::app() in the current runtime, returns its base URL
+pub async fn spawn_api() -> String {
+    init();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = routes::app().await;
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    format!("http://{addr}")
+}
+
+fn mock_app() -> Router {
+    Router::new()
+        .route(
+            "/sap/bc/webdynpro/sap/zba_initss",
+            get(zba_initss).post(zba_initss_login),
+        )
+        .route("/index/login", get(index_login))
+        .route("/acwork/index", get(acwork_index))
+        .route("/acwork/expproc", get(acwork_expproc))
+        .route("/acwork/cancelproc", get(acwork_cancelproc))
+        .route("/acwork/mscoredist", get(mscoredist))
+        // gettimeline only takes the user, everything else in dash/ needs the hash
+        .route("/dash/gettimeline", get(gettimeline))
+        .route("/dash/{endpoint}", get(dash))
+        .route("/room/json", get(room_json))
+        // "same" stays on localhost, "cross" goes to the same mock as 127.0.0.1,
+        // which reqwest treats as another host
+        .route("/redirect/{target}", get(redirect))
+}
+
+fn fixture(name: &str) -> &'static str {
+    match name {
+        "zba_initss" => include_str!("fixtures/zba_initss.html"),
+        "index_login" => include_str!("fixtures/index_login.html"),
+        "acwork_index" => include_str!("fixtures/acwork_index.html"),
+        "acwork_expproc" => include_str!("fixtures/acwork_expproc.html"),
+        "acwork_cancelproc" => include_str!("fixtures/acwork_cancelproc.html"),
+        "acwork_mscoredist" => include_str!("fixtures/acwork_mscoredist.json"),
+        "dash_getcp" => include_str!("fixtures/dash_getcp.json"),
+        "dash_getfs" => include_str!("fixtures/dash_getfs.json"),
+        "dash_getexamstats" => include_str!("fixtures/dash_getexamstats.json"),
+        "dash_getreminders" => include_str!("fixtures/dash_getreminders.json"),
+        "dash_gettimeline" => include_str!("fixtures/dash_gettimeline.json"),
+        "room_json" => include_str!("fixtures/room_json.json"),
+        _ => panic!("unknown fixture {name}"),
+    }
+}
+
+fn html(name: &str) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        fixture(name),
+    )
+        .into_response()
+}
+
+fn json(name: &str) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], fixture(name)).into_response()
+}
+
+fn has_session(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .any(|cookie| cookie.contains(MOCK_SESSION))
+}
+
+fn has_hash(query: &HashMap<String, String>, user_key: &str) -> bool {
+    query.get(user_key).map(String::as_str) == Some(MOCK_USER)
+        && query.get("hash").map(String::as_str) == Some(MOCK_HASH)
+}
+
+// like SAP: the login page for new sessions, a 500 for live ones
+async fn zba_initss(headers: HeaderMap) -> Response {
+    if has_session(&headers) {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    } else {
+        html("zba_initss")
+    }
+}
+
+async fn zba_initss_login(Form(form): Form<HashMap<String, String>>) -> Response {
+    let field = |name: &str| form.get(name).map(String::as_str);
+
+    if field("sap-user") == Some(MOCK_MAINTENANCE_USER) {
+        return "Wartungsarbeiten".into_response();
+    }
+    if field("sap-user") != Some(MOCK_USER)
+        || field("sap-password") != Some(MOCK_PASSWORD)
+        || field("sap-login-XSRF") != Some(MOCK_XSRF)
+    {
+        return html("zba_initss");
+    }
+
+    (
+        [(
+            header::SET_COOKIE,
+            format!("{MOCK_SESSION}; Domain=localhost; Path=/"),
+        )],
+        "Angemeldet",
+    )
+        .into_response()
+}
+
+async fn index_login(headers: HeaderMap) -> Response {
+    session_page(&headers, "index_login")
+}
+
+async fn acwork_index(headers: HeaderMap) -> Response {
+    session_page(&headers, "acwork_index")
+}
+
+async fn acwork_expproc(headers: HeaderMap) -> Response {
+    session_page(&headers, "acwork_expproc")
+}
+
+async fn acwork_cancelproc(headers: HeaderMap) -> Response {
+    session_page(&headers, "acwork_cancelproc")
+}
+
+fn session_page(headers: &HeaderMap, name: &str) -> Response {
+    if has_session(headers) {
+        html(name)
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+async fn dash(
+    Path(endpoint): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if !has_hash(&query, "user") {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match endpoint.as_str() {
+        "getcp" => json("dash_getcp"),
+        "getfs" => json("dash_getfs"),
+        "getexamstats" => json("dash_getexamstats"),
+        "getreminders" => json("dash_getreminders"),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn mscoredist(headers: HeaderMap, Query(query): Query<HashMap<String, String>>) -> Response {
+    if !has_session(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    if ["module", "peryr", "perid"]
+        .iter()
+        .any(|key| !query.contains_key(*key))
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    json("acwork_mscoredist")
+}
+
+async fn gettimeline(Query(query): Query<HashMap<String, String>>) -> Response {
+    if query.get("user").map(String::as_str) == Some(MOCK_USER) {
+        json("dash_gettimeline")
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+async fn room_json(Query(query): Query<HashMap<String, String>>) -> Response {
+    let window = ["start", "end"].map(|key| query.get(key).and_then(|v| v.parse::<i64>().ok()));
+
+    match window {
+        [Some(start), Some(end)] if has_hash(&query, "userid") && start < end => json("room_json"),
+        [Some(_), Some(_)] if has_hash(&query, "userid") => StatusCode::BAD_REQUEST.into_response(),
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+async fn redirect(Path(target): Path<String>) -> Response {
+    let origin = MOCK_ORIGIN.get().unwrap();
+
+    match target.as_str() {
+        "same" => Redirect::temporary(&format!("{origin}/index/login")).into_response(),
+        "cross" => Redirect::temporary(&format!(
+            "{}/index/login",
+            origin.replace("localhost", "127.0.0.1")
+        ))
+        .into_response(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,18 @@
+mod e2e;
+mod gpa;
+mod grade_changes;
+#[cfg(feature = "grade-watcher")]
+mod grade_watcher;
+mod handlers;
+mod jwt_keys;
+mod mock_campusdual;
+mod revocation;
+mod scrapers;
+mod vault;
+
+use crate::{campus_backend::req_client_funcs::extract_grades, types::CampusDualGrade};
+
+// The parsed grades_mixed page, shared by the GPA and grade change tests
+fn fixture_grades() -> Vec<CampusDualGrade> {
+    extract_grades(scrapers::read_fixture("grades_mixed")).unwrap()
+}
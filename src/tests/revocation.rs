@@ -0,0 +1,30 @@
+use std::{env, fs};
+
+use chrono::Utc;
+
+use crate::{
+    encryption::generate_id,
+    revocation::{FileRevocationStore, RevocationStore},
+};
+
+#[test]
+fn file_store_survives_reopening_and_drops_expired_entries() {
+    let path = env::temp_dir().join(format!("revocations-{}", generate_id()));
+    let now = Utc::now().timestamp() as usize;
+
+    let store = FileRevocationStore::open(&path).unwrap();
+    store.revoke("still-revoked", now + 3600).unwrap();
+    store.revoke("expired", now - 1).unwrap();
+    assert!(store.is_revoked("still-revoked"));
+    assert!(!store.is_revoked("unknown"));
+
+    let store = FileRevocationStore::open(&path).unwrap();
+    assert!(store.is_revoked("still-revoked"));
+    assert!(!store.is_revoked("expired"));
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("still-revoked {}\n", now + 3600)
+    );
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,92 @@
+use std::{env, fs, path::PathBuf};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::campus_backend::req_client_funcs::{
+    extract_exam_signup_options, extract_exam_verfahren_options, extract_grades,
+};
+
+fn fixture_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/tests/fixtures/scrapers")
+}
+
+pub(super) fn read_fixture(name: &str) -> String {
+    fs::read_to_string(fixture_dir().join(format!("{name}.html"))).unwrap()
+}
+
+// Compares against <name>.expected.json, UPDATE_SNAPSHOTS=1 rewrites it instead
+fn assert_snapshot(name: &str, actual: &impl Serialize) {
+    let path = fixture_dir().join(format!("{name}.expected.json"));
+    let actual = serde_json::to_value(actual).unwrap();
+
+    if env::var("UPDATE_SNAPSHOTS").is_ok() {
+        fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return;
+    }
+
+    let expected: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(
+        actual == expected,
+        "{name} differs from its snapshot, got:\n{}",
+        serde_json::to_string_pretty(&actual).unwrap()
+    );
+}
+
+#[test]
+fn grades_mixed() {
+    let grades = extract_grades(read_fixture("grades_mixed")).unwrap();
+    assert_snapshot("grades_mixed", &grades);
+}
+
+#[test]
+fn grades_empty_table() {
+    let grades = extract_grades(read_fixture("grades_empty_table")).unwrap();
+    assert_snapshot("grades_empty_table", &grades);
+}
+
+#[test]
+fn grades_no_table() {
+    let grades = extract_grades(read_fixture("grades_no_table")).unwrap();
+    assert_snapshot("grades_no_table", &grades);
+}
+
+#[tokio::test]
+async fn signup_mixed() {
+    let options = extract_exam_signup_options(read_fixture("signup_mixed"))
+        .await
+        .unwrap();
+    assert_snapshot("signup_mixed", &options);
+}
+
+#[tokio::test]
+async fn signup_empty_table() {
+    let options = extract_exam_signup_options(read_fixture("signup_empty_table"))
+        .await
+        .unwrap();
+    assert_snapshot("signup_empty_table", &options);
+}
+
+#[tokio::test]
+async fn verfahren_mixed() {
+    let options = extract_exam_verfahren_options(read_fixture("verfahren_mixed"))
+        .await
+        .unwrap();
+    assert_snapshot("verfahren_mixed", &options);
+}
+
+#[tokio::test]
+async fn verfahren_empty_table() {
+    let options = extract_exam_verfahren_options(read_fixture("verfahren_empty_table"))
+        .await
+        .unwrap();
+    assert_snapshot("verfahren_empty_table", &options);
+}
+
+#[tokio::test]
+async fn verfahren_malformed_rows() {
+    let options = extract_exam_verfahren_options(read_fixture("verfahren_malformed"))
+        .await
+        .unwrap();
+    assert_snapshot("verfahren_malformed", &options);
+}
@@ -0,0 +1,30 @@
+use std::{env, fs};
+
+use chrono::Utc;
+
+use crate::{encryption::generate_id, vault::SecretVault};
+
+#[test]
+fn vault_survives_reopening_but_not_a_wrong_key() {
+    let path = env::temp_dir().join(format!("vault-{}", generate_id()));
+    let key = *b"test-vault-key-of-exact-32-bytes";
+
+    let vault = SecretVault::open(key, &path).unwrap();
+    let now = Utc::now().timestamp() as usize;
+    let kept = vault.insert("kept", None).unwrap();
+    let removed = vault.insert("removed", Some(now + 3600)).unwrap();
+    let expired = vault.insert("expired", Some(now - 1)).unwrap();
+    vault.remove(&removed).unwrap();
+    assert_eq!(vault.get(&kept).as_deref(), Some("kept"));
+    assert_eq!(vault.get(&expired), None);
+    assert!(!fs::read_to_string(&path).unwrap().contains("kept"));
+
+    let vault = SecretVault::open(key, &path).unwrap();
+    assert_eq!(vault.get(&kept).as_deref(), Some("kept"));
+    assert_eq!(vault.get(&removed), None);
+    assert!(!fs::read_to_string(&path).unwrap().contains(&expired));
+
+    assert!(SecretVault::open(*b"another-key-of-exactly-32-bytes!", &path).is_err());
+
+    fs::remove_file(path).unwrap();
+}
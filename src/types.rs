@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use reqwest::StatusCode;
 use scraper::ElementRef;
 use serde::{Deserialize, Serialize};
@@ -11,13 +14,28 @@ pub struct CampusLoginData {
 // JWT Claims
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Claims {
-    pub exp: usize,     // expiration time
-    pub iat: usize,     // issued at
-    pub nonce: String,  // AES nonce
-    pub cipher: String, // AES cipher (CdAuthData)
+    pub exp: usize,              // expiration time
+    pub iat: usize,              // issued at
+    pub iss: String,             // issuer
+    pub aud: String,             // audience
+    pub typ: TokenType,          // access or refresh
+    pub jti: String,             // shared by all tokens of one sign-in, see auth::signout
+    pub sub: String,             // CampusDual user number
+    pub seminar_group: String,   // e.g. "CS22-1", for services that only verify the token
+    pub nonce: String,           // AES nonce
+    pub cipher: String,          // AES cipher (CdAuthData)
+    pub sealed_password: String, // only opens with the client's revive secret
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    Access,
+    Refresh,
 }
 
 // API Response type
+#[derive(Debug)]
 pub struct ResponseError {
     pub message: String,
     pub status_code: StatusCode,
@@ -26,7 +44,22 @@ pub struct ResponseError {
 #[derive(serde::Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserBasicInfo,
+    // kept by the client and sent as X-Revive-Secret, the server never stores it
+    pub revive_secret: String,
+}
+
+// Shared by the access and refresh tokens of one sign-in
+#[derive(Clone)]
+pub struct TokenSession {
+    pub jti: String,
+    pub seminar_group: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenRefreshResponse {
+    pub token: String,
 }
 
 // Inserted by the auth middleware into the request extension
@@ -35,13 +68,58 @@ pub struct CdAuthData {
     pub cookie: String,
     pub hash: String,
     pub user: String,
-    pub password: String,
+}
+
+// Inserted by the auth middleware, the jti of the token
+#[derive(Debug, Clone)]
+pub struct TokenId(pub String);
+
+// Inserted by the auth middleware next to CdAuthData, see encryption::seal_password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedPassword(pub String);
+
+// AES-encrypted into the path of subscribable calendar feeds
+#[derive(Serialize, Deserialize)]
+pub struct FeedTokenData {
+    pub id: String,
+    // jti of the session the feed was created in, signing out ends the feed as well
+    pub jti: String,
+    // unix timestamp, no later than the session's revocation would be forgotten
+    pub exp: usize,
+    pub scope: FeedScope,
+    pub cd_auth_data: CdAuthData,
+    // the exams feed has to log in again, a subscription URL can't send headers,
+    // so the revive secret is kept in the vault
+    pub sealed_password: SealedPassword,
+    pub vault_id: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedScope {
+    // read-only calendar data, nothing else
+    Calendar,
+}
+
+// Paths are relative to the API root, the API doesn't know its public URL
+#[derive(Serialize)]
+pub struct FeedLinks {
+    pub id: String,
+    pub token: String,
+    pub stundenplan: String,
+    pub exams: String,
+    pub timeline: String,
+}
+
+#[derive(Deserialize)]
+pub struct FeedRevokeRequest {
+    pub token: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CampusDualGrade {
     pub name: String,
-    pub grade: String,
+    pub grade: Grade,
     pub total_passed: Option<bool>,
     pub credit_points: i32,
     pub akad_period: String,
@@ -51,15 +129,60 @@ pub struct CampusDualGrade {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CampusDualSubGrade {
     pub name: String,
-    pub grade: String,
+    pub grade: Grade,
     pub passed: Option<bool>,
-    pub beurteilung: String,
-    pub bekanntgabe: String,
+    pub beurteilung: Option<NaiveDate>,
+    pub bekanntgabe: Option<NaiveDate>,
     pub wiederholung: Option<String>,
     pub akad_period: String,
     pub internal_metadata: Option<SubGradeMetadata>,
 }
 
+// {"kind": "numeric", "value": 2.3}, {"kind": "passed"}, ...
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum Grade {
+    // German scale, 1.0 to 5.0
+    Numeric(f64),
+    // pass/fail modules
+    Passed,
+    Failed,
+    NotGraded,
+}
+
+impl Grade {
+    // Grade cell text of acwork/index, e.g. "2,3", "mit Erfolg" or "" for pending ones
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+
+        if let Some(grade) = text
+            .replace(',', ".")
+            .parse()
+            .ok()
+            .filter(|grade| (1.0..=5.0).contains(grade))
+        {
+            return Grade::Numeric(grade);
+        }
+
+        match text {
+            "mit Erfolg" | "bestanden" | "erfolgreich" => Grade::Passed,
+            "ohne Erfolg" | "nicht bestanden" | "nicht erfolgreich" => Grade::Failed,
+            "" | "?" => Grade::NotGraded,
+            _ => {
+                log::warn!("unknown grade text: {text}");
+                Grade::NotGraded
+            }
+        }
+    }
+
+    pub fn numeric(&self) -> Option<f64> {
+        match self {
+            Grade::Numeric(grade) => Some(*grade),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubGradeMetadata {
     pub module: String,
@@ -105,6 +228,12 @@ pub struct CampusDualVerfahrenOption {
     pub internal_metadata: Option<ExamRegistrationMetadata>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CampusDualVerfahrenOptions {
+    pub options: Vec<CampusDualVerfahrenOption>,
+    pub warnings: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct UserBasicInfo {
     pub first_name: String,
@@ -114,6 +243,20 @@ pub struct UserBasicInfo {
     pub user: String,
 }
 
+// Response of /get_ects
+#[derive(Debug, Serialize)]
+pub struct CreditPoints {
+    pub earned: u32,
+    // total the degree needs, not known to CampusDual
+    pub required: u32,
+}
+
+// Response of /get_fachsem
+#[derive(Debug, Serialize)]
+pub struct Fachsemester {
+    pub semester: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CdExamStats {
     #[serde(rename(deserialize = "EXAMS"))]
@@ -141,91 +284,147 @@ pub struct CdExamStats {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StundenplanItem {
     #[serde(rename = "allDay")]
-    all_day: bool,
+    pub all_day: bool,
     pub color: String,
     pub font_color: Option<String>,
-    description: String,
+    pub description: String,
     editable: bool,
     pub end: i64,
-    instructor: String,
-    remarks: String,
-    room: String,
-    sinstructor: String,
-    sroom: String,
+    pub instructor: String,
+    pub remarks: String,
+    pub room: String,
+    pub sinstructor: String,
+    pub sroom: String,
     pub start: i64,
     pub title: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct LatestReminder {
-    #[serde(rename(deserialize = "ACAD_SESSION"))]
-    acad_session: String,
-    #[serde(rename(deserialize = "ACAD_YEAR"))]
-    acad_year: String,
-    #[serde(rename(deserialize = "AGRDATE"))]
-    agrdate: String,
-    #[serde(rename(deserialize = "AGRTYPE"))]
-    agrtype: String,
-    #[serde(rename(deserialize = "AWOBJECT"))]
-    awobject: String,
-    #[serde(rename(deserialize = "AWOBJECT_SHORT"))]
-    awobject_short: String,
-    #[serde(rename(deserialize = "AWOTYPE"))]
-    awotype: String,
-    #[serde(rename(deserialize = "AWSTATUS"))]
-    awstatus: String,
-    #[serde(rename(deserialize = "BOOKDATE"))]
-    bookdate: String,
-    #[serde(rename(deserialize = "BOOKREASON"))]
-    bookreason: String,
-    #[serde(rename(deserialize = "CPGRADED"))]
-    cpgraded: String,
-    #[serde(rename(deserialize = "CPUNIT"))]
-    cpunit: String,
-    #[serde(rename(deserialize = "GRADESYMBOL"))]
-    gradesymbol: String,
+// Query of /get_stundenplan, both dates are inclusive
+#[derive(Debug, Deserialize)]
+pub struct StundenplanQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UpcomingReminder {
-    #[serde(rename(deserialize = "BEGUZ"))]
-    beguz: String,
-    #[serde(rename(deserialize = "COMMENT"))]
-    comment: String,
-    #[serde(rename(deserialize = "ENDUZ"))]
-    enduz: String,
-    #[serde(rename(deserialize = "EVDAT"))]
-    evdat: String,
-    #[serde(rename(deserialize = "INSTRUCTOR"))]
-    instructor: String,
-    #[serde(rename(deserialize = "LOCATION"))]
-    location: String,
-    #[serde(rename(deserialize = "OBJID"))]
-    objid: String,
-    #[serde(rename(deserialize = "ROOM"))]
-    room: String,
-    #[serde(rename(deserialize = "SINSTRUCTOR"))]
-    sinstructor: String,
-    #[serde(rename(deserialize = "SM_SHORT"))]
-    sm_short: String,
-    #[serde(rename(deserialize = "SM_STEXT"))]
-    sm_stext: String,
-    #[serde(rename(deserialize = "SROOM"))]
-    sroom: String,
+// dash/getreminders as CampusDual sends it, see Reminders for the API response
+#[derive(Debug, Deserialize)]
+pub struct CampusLatestReminder {
+    #[serde(rename = "ACAD_SESSION")]
+    pub acad_session: String,
+    #[serde(rename = "ACAD_YEAR")]
+    pub acad_year: String,
+    #[serde(rename = "AGRDATE")]
+    pub agrdate: String,
+    #[serde(rename = "AGRTYPE")]
+    pub agrtype: String,
+    #[serde(rename = "AWOBJECT")]
+    pub awobject: String,
+    #[serde(rename = "AWOBJECT_SHORT")]
+    pub awobject_short: String,
+    #[serde(rename = "AWOTYPE")]
+    pub awotype: String,
+    #[serde(rename = "AWSTATUS")]
+    pub awstatus: String,
+    #[serde(rename = "BOOKDATE")]
+    pub bookdate: String,
+    #[serde(rename = "BOOKREASON")]
+    pub bookreason: String,
+    #[serde(rename = "CPGRADED")]
+    pub cpgraded: String,
+    #[serde(rename = "CPUNIT")]
+    pub cpunit: String,
+    #[serde(rename = "GRADESYMBOL")]
+    pub gradesymbol: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
+pub struct CampusUpcomingReminder {
+    #[serde(rename = "BEGUZ")]
+    pub beguz: String,
+    #[serde(rename = "COMMENT")]
+    pub comment: String,
+    #[serde(rename = "ENDUZ")]
+    pub enduz: String,
+    #[serde(rename = "EVDAT")]
+    pub evdat: String,
+    #[serde(rename = "INSTRUCTOR")]
+    pub instructor: String,
+    #[serde(rename = "LOCATION")]
+    pub location: String,
+    #[serde(rename = "OBJID")]
+    pub objid: String,
+    #[serde(rename = "ROOM")]
+    pub room: String,
+    #[serde(rename = "SINSTRUCTOR")]
+    pub sinstructor: String,
+    #[serde(rename = "SM_SHORT")]
+    pub sm_short: String,
+    #[serde(rename = "SM_STEXT")]
+    pub sm_stext: String,
+    #[serde(rename = "SROOM")]
+    pub sroom: String,
+}
+
+#[derive(Debug, Deserialize)]
 pub struct CampusReminders {
-    #[serde(rename(deserialize = "ELECTIVES"))]
-    electives: i64,
-    #[serde(rename(deserialize = "EXAMS"))]
-    exams: i64,
-    #[serde(rename(deserialize = "LATEST"))]
-    latest: Vec<LatestReminder>,
-    #[serde(rename(deserialize = "SEMESTER"))]
-    semester: i64,
-    #[serde(rename(deserialize = "UPCOMING"))]
-    upcoming: Vec<UpcomingReminder>,
+    #[serde(rename = "ELECTIVES")]
+    pub electives: i64,
+    #[serde(rename = "EXAMS")]
+    pub exams: i64,
+    #[serde(rename = "LATEST")]
+    pub latest: Vec<CampusLatestReminder>,
+    #[serde(rename = "SEMESTER")]
+    pub semester: i64,
+    #[serde(rename = "UPCOMING")]
+    pub upcoming: Vec<CampusUpcomingReminder>,
+}
+
+// Response of /get_reminders
+#[derive(Debug, Serialize)]
+pub struct Reminders {
+    pub semester: i64,
+    // number of open exams / electives
+    pub exams: i64,
+    pub electives: i64,
+    // recently published grades
+    pub latest: Vec<LatestGradeReminder>,
+    // next lectures and exams
+    pub upcoming: Vec<UpcomingEventReminder>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LatestGradeReminder {
+    pub name: String,
+    pub short_name: String,
+    pub academic_year: String,
+    pub academic_session: String,
+    pub graded_on: Option<NaiveDate>,
+    pub booked_on: Option<NaiveDate>,
+    // None for pass/fail grades, the symbol is kept as is
+    pub grade: Option<f32>,
+    pub grade_symbol: String,
+    pub credit_points: Option<f32>,
+    pub credit_point_unit: String,
+    // SAP codes: assessment type, object type, status and booking reason
+    pub assessment_type: String,
+    pub object_type: String,
+    pub status: String,
+    pub booking_reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpcomingEventReminder {
+    pub id: String,
+    pub title: String,
+    pub short_title: String,
+    pub start: Option<DateTime<FixedOffset>>,
+    pub end: Option<DateTime<FixedOffset>>,
+    pub instructor: String,
+    pub short_instructor: String,
+    pub room: String,
+    pub short_room: String,
+    pub location: String,
+    pub comment: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -248,6 +447,39 @@ pub struct CampusTimelineEvent {
     pub duration: Option<bool>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineEventKind {
+    Fachsemester,
+    Theoriesemester,
+    Praxissemester,
+    Special,
+    Other,
+}
+
+impl TimelineEventKind {
+    // CampusDual only tells the phases apart by their colour
+    pub fn from_cd_color(color: &str) -> Self {
+        match color {
+            "#fcbe04" => TimelineEventKind::Fachsemester,
+            "#0070a3" => TimelineEventKind::Theoriesemester,
+            "#119911" => TimelineEventKind::Praxissemester,
+            "#880000" => TimelineEventKind::Special,
+            _ => TimelineEventKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    pub kind: TimelineEventKind,
+    pub name: String,
+    pub description: String,
+    pub color: String,
+    pub start: Option<DateTime<FixedOffset>>,
+    pub end: Option<DateTime<FixedOffset>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct ExportTimelineEvents {
     pub fachsemester: Vec<ExportTimelineEvent>,
@@ -265,6 +497,38 @@ pub struct ExportTimelineEvent {
     pub end: String,
 }
 
+// Response of /dashboard, every section fails on its own
+#[derive(Serialize)]
+pub struct Dashboard {
+    pub ects: DashboardSection<CreditPoints>,
+    pub fachsem: DashboardSection<Fachsemester>,
+    pub examstats: DashboardSection<CdExamStats>,
+    pub reminders: DashboardSection<Reminders>,
+    pub timeline: DashboardSection<ExportTimelineEvents>,
+}
+
+// Either data or the error message of the equivalent single endpoint
+#[derive(Serialize)]
+pub struct DashboardSection<T> {
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+impl<T> From<Result<T, ResponseError>> for DashboardSection<T> {
+    fn from(result: Result<T, ResponseError>) -> Self {
+        match result {
+            Ok(data) => DashboardSection {
+                data: Some(data),
+                error: None,
+            },
+            Err(err) => DashboardSection {
+                data: None,
+                error: Some(err.message),
+            },
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct CdExamDetails {
     #[serde(rename(deserialize = "EV_AGRTYPE_TEXT"))]
@@ -326,6 +590,71 @@ pub struct CdGradeStatEntry {
     pub count: i64,
 }
 
+// Query of /get_grade_changes, without either one every grade counts as changed
+#[derive(Deserialize)]
+pub struct GradeChangesQuery {
+    // grades announced (bekanntgabe) after this day
+    pub since: Option<NaiveDate>,
+    // fingerprint of the previous response
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GradeChanges {
+    // send this back next time
+    pub fingerprint: String,
+    // only changed modules, each with only its changed subgrades
+    pub grades: Vec<CampusDualGrade>,
+}
+
+// Body of POST /get_gpa, hypothetical grades for modules that aren't passed yet
+#[derive(Deserialize)]
+pub struct GpaRequest {
+    pub what_if: Vec<WhatIfGrade>,
+}
+
+#[derive(Deserialize)]
+pub struct WhatIfGrade {
+    // CampusDualGrade.name
+    pub module: String,
+    pub grade: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GpaSummary {
+    pub overall: GpaStats,
+    // oldest first
+    pub semesters: Vec<SemesterGpa>,
+    // whether what-if grades went into the numbers
+    pub hypothetical: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct GpaStats {
+    // ECTS-weighted, None without any graded module
+    pub gpa: Option<f64>,
+    // credit points behind the gpa
+    pub graded_credit_points: i32,
+    // credit points of all passed modules, pass/fail ones included
+    pub earned_credit_points: i32,
+    pub graded_modules: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SemesterGpa {
+    pub akad_period: String,
+    #[serde(flatten)]
+    pub stats: GpaStats,
+}
+
+// Body of /get_gradestats, grade is the caller's own grade for percentile_rank
+#[derive(Deserialize)]
+pub struct GradeStatsRequest {
+    #[serde(flatten)]
+    pub meta: SubGradeMetadata,
+    pub grade: Option<f32>,
+}
+
 #[derive(Debug, Serialize, Default)]
 pub struct GradeStatsAllStudents {
     pub one: i64,
@@ -333,6 +662,15 @@ pub struct GradeStatsAllStudents {
     pub three: i64,
     pub four: i64,
     pub ronmodus: i64,
+    // counts of grade texts without a German grade, e.g. "mit Erfolg"
+    pub other: BTreeMap<String, i64>,
+    // the statistics below only cover one to ronmodus (1.0 to 5.0) and are None without any
+    pub mean: Option<f32>,
+    pub median: Option<f32>,
+    // in percent
+    pub pass_rate: Option<f32>,
+    // percent of the cohort with a worse grade, counting half of the own grade's students
+    pub percentile_rank: Option<f32>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atomic_write::write_atomically,
+    encryption::{decrypt_urlsafe_with, encrypt_urlsafe_with, generate_id},
+};
+
+// Revive secrets for logins the server does on its own (exam feeds, grade watcher), by
+// random ID. They're encrypted with VAULT_KEY instead of AES_KEY, so the AES key plus a
+// feed token (or the watcher store) isn't enough to unseal a password
+pub struct SecretVault {
+    key: [u8; 32],
+    // in-memory only without a file, the secrets are gone after a restart
+    path: Option<PathBuf>,
+    secrets: Mutex<HashMap<String, VaultEntry>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultEntry {
+    // encrypted with the vault key
+    secret: String,
+    // unix timestamp, e.g. the feed's expiry. None until removed
+    expires_at: Option<usize>,
+}
+
+impl VaultEntry {
+    fn is_expired(&self, now: usize) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl SecretVault {
+    // Random key, nothing outlives the process
+    pub fn in_memory() -> Self {
+        let mut key = [0u8; 32];
+        rand::rng().fill(&mut key);
+
+        SecretVault {
+            key,
+            path: None,
+            secrets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Fails if the file can't be read or wasn't written with this key,
+    // so a wrong key can't silently drop every secret on the next write
+    pub fn open(key: [u8; 32], path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+
+        let mut secrets = HashMap::new();
+        if path.exists() {
+            secrets =
+                serde_json::from_str::<HashMap<String, VaultEntry>>(&fs::read_to_string(&path)?)?;
+            for entry in secrets.values() {
+                decrypt_urlsafe_with(&key, &entry.secret)
+                    .context("vault is not encrypted with VAULT_KEY")?;
+            }
+
+            let now = Utc::now().timestamp() as usize;
+            secrets.retain(|_, entry| !entry.is_expired(now));
+        }
+
+        // rewritten right away, so expired secrets don't linger in the file
+        let vault = SecretVault {
+            key,
+            path: Some(path),
+            secrets: Mutex::new(secrets),
+        };
+        vault.save(&vault.secrets.lock().unwrap())?;
+
+        Ok(vault)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.path.is_some()
+    }
+
+    // Returns the ID to fetch the secret with. Expired secrets are dropped along the way
+    pub fn insert(&self, secret: &str, expires_at: Option<usize>) -> Result<String> {
+        let secret = encrypt_urlsafe_with(&self.key, secret)
+            .map_err(|_| anyhow!("unable to encrypt the secret"))?;
+        let id = generate_id();
+        let now = Utc::now().timestamp() as usize;
+
+        let mut secrets = self.secrets.lock().unwrap();
+        secrets.retain(|_, entry| !entry.is_expired(now));
+        secrets.insert(id.clone(), VaultEntry { secret, expires_at });
+        self.save(&secrets)?;
+
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<String> {
+        let secrets = self.secrets.lock().unwrap();
+        let entry = secrets
+            .get(id)
+            .filter(|entry| !entry.is_expired(Utc::now().timestamp() as usize))?;
+
+        decrypt_urlsafe_with(&self.key, &entry.secret).ok()
+    }
+
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let mut secrets = self.secrets.lock().unwrap();
+        if secrets.remove(id).is_some() {
+            self.save(&secrets)?;
+        }
+
+        Ok(())
+    }
+
+    fn save(&self, secrets: &HashMap<String, VaultEntry>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        Ok(write_atomically(path, serde_json::to_string(secrets)?)?)
+    }
+}